@@ -3,7 +3,8 @@ use std::{env, fs, path::PathBuf};
 use protox::prost::Message;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let file_descriptors = protox::compile(["proto/lucle.proto"], ["."]).unwrap();
+    let file_descriptors =
+        protox::compile(["proto/lucle.proto", "proto/speedupdate.proto"], ["."]).unwrap();
 
     let file_descriptor_path = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR not set"))
         .join("file_descriptor_set.bin");
@@ -14,6 +15,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::configure()
         .skip_protoc_run()
         .file_descriptor_set_path(&file_descriptor_path)
-        .compile_protos_with_config(config, &["proto/lucle.proto"], &["proto"])?;
+        .compile_protos_with_config(
+            config,
+            &["proto/lucle.proto", "proto/speedupdate.proto"],
+            &["proto"],
+        )?;
     Ok(())
 }
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE refresh_tokens (
+  id VARCHAR(32) PRIMARY KEY,
+  user_id INT NOT NULL,
+  expires_at TIMESTAMP NOT NULL,
+  revoked BOOLEAN NOT NULL DEFAULT FALSE,
+  INDEX refresh_tokens_user_id (user_id)
+);
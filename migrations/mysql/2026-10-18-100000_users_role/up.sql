@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN role ENUM("admin", "maintainer", "member") NOT NULL DEFAULT "member";
+
+-- The user created by the install wizard administrates the instance
+UPDATE users SET role = "admin" ORDER BY id LIMIT 1;
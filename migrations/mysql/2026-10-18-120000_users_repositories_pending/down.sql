@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM users_repositories WHERE permission = "pending";
+
+ALTER TABLE users_repositories MODIFY permission ENUM("read", "write") NOT NULL;
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE users_repositories MODIFY permission ENUM("read", "write", "pending") NOT NULL;
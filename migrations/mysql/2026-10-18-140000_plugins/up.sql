@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE plugins (
+  name VARCHAR(255) PRIMARY KEY,
+  enabled BOOLEAN NOT NULL DEFAULT TRUE,
+  installed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
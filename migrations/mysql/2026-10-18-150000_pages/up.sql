@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE pages (
+  id INTEGER AUTO_INCREMENT PRIMARY KEY,
+  slug VARCHAR(255) NOT NULL,
+  title VARCHAR(255) NOT NULL,
+  body TEXT NOT NULL,
+  author_id INT NOT NULL,
+  status ENUM("draft", "published") NOT NULL DEFAULT "draft",
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  modified_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  published_at TIMESTAMP NULL,
+  UNIQUE (slug)
+);
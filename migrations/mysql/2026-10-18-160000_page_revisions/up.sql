@@ -0,0 +1,18 @@
+-- Your SQL goes here
+ALTER TABLE pages ADD COLUMN revision INT NOT NULL DEFAULT 0;
+
+CREATE TABLE page_revisions (
+  page_slug VARCHAR(255) NOT NULL,
+  revision INT NOT NULL,
+  title VARCHAR(255) NOT NULL,
+  body TEXT NOT NULL,
+  author_id INT NOT NULL,
+  created_at TIMESTAMP NOT NULL,
+  PRIMARY KEY(page_slug, revision)
+);
+
+-- Existing pages start their history with their current content
+INSERT INTO page_revisions (page_slug, revision, title, body, author_id, created_at)
+SELECT slug, 1, title, body, author_id, modified_at FROM pages;
+
+UPDATE pages SET revision = 1;
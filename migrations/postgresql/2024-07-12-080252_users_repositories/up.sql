@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TYPE permission AS ENUM ('read', 'write', 'pending');
+
+CREATE TABLE users_repositories (
+  user_id INT NOT NULL,
+  repository_name VARCHAR(255) NOT NULL,
+  permission permission NOT NULL,
+  PRIMARY KEY(user_id, repository_name)
+);
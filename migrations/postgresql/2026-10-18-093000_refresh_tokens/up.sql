@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE refresh_tokens (
+  id VARCHAR(32) PRIMARY KEY,
+  user_id INT NOT NULL,
+  expires_at TIMESTAMP NOT NULL,
+  revoked BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX refresh_tokens_user_id ON refresh_tokens (user_id);
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TYPE user_role AS ENUM ('admin', 'maintainer', 'member');
+
+ALTER TABLE users ADD COLUMN role user_role NOT NULL DEFAULT 'member';
+
+-- The user created by the install wizard administrates the instance
+UPDATE users SET role = 'admin' WHERE id = (SELECT MIN(id) FROM users);
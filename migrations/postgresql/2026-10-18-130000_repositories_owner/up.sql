@@ -0,0 +1,8 @@
+-- Your SQL goes here
+ALTER TABLE repositories ADD COLUMN owner_id INTEGER;
+
+-- The member who registered a repository is its first writer
+UPDATE repositories SET owner_id = (
+  SELECT MIN(user_id) FROM users_repositories
+  WHERE repository_name = repositories.name AND permission = 'write'
+);
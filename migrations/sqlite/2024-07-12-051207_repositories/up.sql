@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE repositories (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  name VARCHAR(255) NOT NULL,
+  created_at TIMESTAMP NOT NULL,
+  UNIQUE (name)
+);
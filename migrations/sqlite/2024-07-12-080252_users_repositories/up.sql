@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE users_repositories (
+  user_id INTEGER NOT NULL,
+  repository_name VARCHAR(255) NOT NULL,
+  permission TEXT CHECK(permission IN ('read', 'write', 'pending')) NOT NULL,
+  PRIMARY KEY(user_id, repository_name)
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE page_revisions;
+
+ALTER TABLE pages DROP COLUMN revision;
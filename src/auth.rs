@@ -0,0 +1,130 @@
+use crate::errors::Error;
+use crate::jwt::{JwtKeys, Scope};
+use crate::models::{Role, User};
+use crate::storage::Storage;
+use futures_util::future::{ready, Either, Ready};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tonic::{
+    body::BoxBody,
+    codegen::http::{header::AUTHORIZATION, HeaderMap, Request, Response},
+    Status,
+};
+use tower::{Layer, Service};
+
+/// Methods callable without an access token: the install wizard and the
+/// ones a user calls before having a token.
+const PUBLIC_METHODS: &[&str] = &[
+    "/luclerpc.Lucle/create_db",
+    "/luclerpc.Lucle/create_user",
+    "/luclerpc.Lucle/login",
+    "/luclerpc.Lucle/refresh",
+    "/luclerpc.Lucle/revoke",
+    "/luclerpc.Lucle/is_database_created",
+    "/luclerpc.Lucle/forgot_password",
+    "/luclerpc.Lucle/confirm_password_reset",
+];
+
+/// Authenticated caller, taken from the access token and stored in the
+/// request extensions.
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub username: String,
+}
+
+impl Identity {
+    pub fn from_request<T>(request: &tonic::Request<T>) -> Result<Self, Error> {
+        request
+            .extensions()
+            .get::<Self>()
+            .cloned()
+            .ok_or(Error::Unauthenticated("Missing access token"))
+    }
+
+    /// Load the caller and check its role is one of `roles`. The role is
+    /// read from the database so that a change applies to the next call.
+    pub async fn require_role(&self, storage: &dyn Storage, roles: &[Role]) -> Result<User, Error> {
+        match storage.get_user_by_username(&self.username).await? {
+            Some(user) if roles.contains(&user.role) => Ok(user),
+            Some(_) => Err(Error::PermissionDenied),
+            None => Err(Error::UserNotFound),
+        }
+    }
+}
+
+/// Validate the `authorization: Bearer <token>` header of every gRPC call
+/// and insert the caller's `Identity` in the request extensions. Calls to
+/// methods outside `PUBLIC_METHODS` without a valid token are rejected.
+#[derive(Clone)]
+pub struct AuthLayer {
+    keys: Arc<JwtKeys>,
+}
+
+impl AuthLayer {
+    pub fn new(keys: Arc<JwtKeys>) -> Self {
+        Self { keys }
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = Auth<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Auth {
+            inner,
+            keys: self.keys.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Auth<S> {
+    inner: S,
+    keys: Arc<JwtKeys>,
+}
+
+impl<S, B> Service<Request<B>> for Auth<S>
+where
+    S: Service<Request<B>, Response = Response<BoxBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Either<Ready<Result<S::Response, S::Error>>, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        let public = PUBLIC_METHODS.contains(&request.uri().path());
+        match authenticate(&self.keys, request.headers()) {
+            Ok(identity) => {
+                request.extensions_mut().insert(identity);
+            }
+            // A stale token sent along to a public method, e.g. to refresh, is ignored
+            Err(_) if public => {}
+            Err(err) => {
+                tracing::warn!("{} rejected: {}", request.uri().path(), err);
+                return Either::Left(ready(Ok(Status::from(err).into_http())));
+            }
+        }
+        Either::Right(self.inner.call(request))
+    }
+}
+
+fn authenticate(keys: &JwtKeys, headers: &HeaderMap) -> Result<Identity, Error> {
+    let header = headers
+        .get(AUTHORIZATION)
+        .ok_or(Error::Unauthenticated("Missing access token"))?;
+    let token = header
+        .to_str()
+        .ok()
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(Error::Unauthenticated(
+            "Authorization header is not a bearer token",
+        ))?;
+    let claims = keys.verify(token.trim(), Scope::Access)?;
+    Ok(Identity {
+        username: claims.sub,
+    })
+}
@@ -0,0 +1,306 @@
+use crate::config::{LucleConfig, CONFIG_FILE};
+use crate::errors::Error;
+use crate::mailer::{Mailer, Template};
+use crate::models::Role;
+use crate::{diesel, jwt, storage, user, utils, DbType};
+use clap::{Parser, Subcommand};
+use email_address_parser::EmailAddress;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use tera::Context;
+
+#[derive(Parser)]
+#[command(version, about = "Lucle server")]
+pub struct Cli {
+    /// Configuration file
+    #[arg(short, long, global = true, env = "LUCLE_CONFIG", default_value = CONFIG_FILE)]
+    pub config: PathBuf,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Start the gRPC and HTTP servers (default)
+    Serve,
+    /// Manage the database schema
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateCommand,
+    },
+    /// Manage users
+    User {
+        #[command(subcommand)]
+        action: UserCommand,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Manage the TLS certificates
+    Pki {
+        #[command(subcommand)]
+        action: PkiCommand,
+    },
+    /// Check email delivery
+    Mail {
+        #[command(subcommand)]
+        action: MailCommand,
+    },
+}
+
+impl Command {
+    /// `pki` creates the signing key and `migrate` never signs tokens, both
+    /// run on instances without a key yet.
+    pub fn needs_jwt_keys(&self) -> bool {
+        !matches!(self, Command::Pki { .. } | Command::Migrate { .. })
+    }
+}
+
+#[derive(Subcommand)]
+pub enum MigrateCommand {
+    /// Create the database if needed and apply pending migrations
+    Up,
+    /// Revert the last applied migration
+    Down,
+    /// List applied and pending migrations
+    Status,
+}
+
+#[derive(Subcommand)]
+pub enum UserCommand {
+    /// Create a user
+    Create {
+        username: String,
+        email: String,
+        /// admin, maintainer or member
+        #[arg(long, default_value = "member")]
+        role: Role,
+        /// Language of the emails sent to the user, `[mail] default_locale` when not set
+        #[arg(long)]
+        locale: Option<String>,
+        /// Read from standard input when not set
+        #[arg(long, env = "LUCLE_USER_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// Set a new password for a user
+    ResetPassword {
+        username: String,
+        /// Read from standard input when not set
+        #[arg(long, env = "LUCLE_USER_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// List users
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Validate the configuration file and report every problem
+    Check,
+}
+
+#[derive(Subcommand)]
+pub enum PkiCommand {
+    /// Generate the CA and server certificates, and the token signing key
+    /// when it is missing
+    Init {
+        /// Replace existing certificates
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print the certificate paths and the CA certificate
+    Show,
+    /// Generate a new ES256 key to sign tokens with
+    JwtKey { path: PathBuf },
+}
+
+#[derive(Subcommand)]
+pub enum MailCommand {
+    /// Send a test email with the configured transport
+    Test {
+        to: String,
+        /// password_reset, welcome, join_request or access_granted
+        #[arg(long, default_value = "welcome")]
+        template: Template,
+        #[arg(long)]
+        locale: Option<String>,
+    },
+}
+
+/// Validate the configuration, print the result and return the exit code.
+pub fn check_config(path: &Path) -> i32 {
+    match LucleConfig::load(path) {
+        Ok(_) => {
+            println!("{}: configuration is valid", path.display());
+            0
+        }
+        Err(errors) => {
+            for err in errors {
+                eprintln!("{}", err);
+            }
+            1
+        }
+    }
+}
+
+pub async fn run(command: Command, config: &LucleConfig) -> Result<(), Error> {
+    match command {
+        Command::Serve | Command::Config { .. } => Ok(()),
+        Command::Migrate { action } => migrate(action, config).await,
+        Command::User { action } => manage_user(action, config).await,
+        Command::Pki { action } => pki(action, config),
+        Command::Mail { action } => mail(action, config).await,
+    }
+}
+
+async fn migrate(action: MigrateCommand, config: &LucleConfig) -> Result<(), Error> {
+    let database_url = match config.database.db_type()? {
+        DbType::Mysql(url) | DbType::Postgresql(url) | DbType::Sqlite(url) => url,
+        DbType::Surrealdb(_) => {
+            println!("SurrealDB has no migrations, its schema is applied when it is opened");
+            return Ok(());
+        }
+        DbType::NoDatabase => return Err(Error::NoDatabase),
+    };
+    match action {
+        MigrateCommand::Up => {
+            diesel::create_database(&database_url).await?;
+            println!("Database is up to date");
+        }
+        MigrateCommand::Down => {
+            let version = diesel::revert_last_migration(&database_url).await?;
+            println!("Reverted migration {}", version);
+        }
+        MigrateCommand::Status => {
+            let (applied, pending) = diesel::migration_status(&database_url).await?;
+            for version in applied {
+                println!("[x] {}", version);
+            }
+            for name in pending {
+                println!("[ ] {}", name);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn manage_user(action: UserCommand, config: &LucleConfig) -> Result<(), Error> {
+    let Some(storage) = storage::connect(config.database.db_type()?).await? else {
+        return Err(Error::NoDatabase);
+    };
+    match action {
+        UserCommand::Create {
+            username,
+            email,
+            role,
+            locale,
+            password,
+        } => {
+            if !EmailAddress::is_valid(&email, None) {
+                return Err(Error::EmailNotValid);
+            }
+            let password = read_password(password)?;
+            let locale = locale.unwrap_or_else(|| config.mail.default_locale.clone());
+            user::create_user(&*storage, username.clone(), password, email, role, locale).await?;
+            println!("User {} created", username);
+        }
+        UserCommand::ResetPassword { username, password } => {
+            let password = read_password(password)?;
+            user::set_password(&*storage, &username, &password).await?;
+            println!("Password of {} changed", username);
+        }
+        UserCommand::List => {
+            for user in user::list_users(&*storage).await? {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    user.id,
+                    user.username,
+                    user.email,
+                    user.role.as_str()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn pki(action: PkiCommand, config: &LucleConfig) -> Result<(), Error> {
+    let dir = &config.tls.directory;
+    match action {
+        PkiCommand::Init { force } => {
+            if utils::init_pki(dir, force)? {
+                println!("Certificates written to {}", dir.display());
+            } else {
+                println!(
+                    "Certificates already exist in {}, use --force to replace them",
+                    dir.display()
+                );
+            }
+            let private_key = &config.jwt.private_key;
+            if !private_key.exists() {
+                jwt::generate_key(private_key)?;
+                println!("Signing key written to {}", private_key.display());
+            }
+        }
+        PkiCommand::Show => {
+            for file in ["ca_cert.pem", "server_cert.pem", "server_private_key.pem"] {
+                let path = dir.join(file);
+                let state = if path.exists() { "present" } else { "missing" };
+                println!("{}: {}", path.display(), state);
+            }
+            if let Ok(ca_cert) = std::fs::read_to_string(dir.join("ca_cert.pem")) {
+                print!("{}", ca_cert);
+            }
+        }
+        PkiCommand::JwtKey { path } => {
+            jwt::generate_key(&path)?;
+            println!("Signing key written to {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+async fn mail(action: MailCommand, config: &LucleConfig) -> Result<(), Error> {
+    let mailer = Mailer::new(&config.mail)?;
+    match action {
+        MailCommand::Test {
+            to,
+            template,
+            locale,
+        } => {
+            // Sample values for every variable the templates use
+            let mut context = Context::new();
+            context.insert("username", &to);
+            context.insert("link", &config.listeners.public_url);
+            context.insert("expires_in", &(config.jwt.reset_token_lifetime / 60));
+            context.insert("requester", "someone");
+            context.insert("repository", "example");
+            context.insert("permission", "read");
+            let locale = locale.unwrap_or_else(|| config.mail.default_locale.clone());
+            mailer.send(template, &to, &locale, &context).await?;
+            // The memory transport keeps what it would have sent, show it
+            for email in mailer.sent() {
+                println!("{}", String::from_utf8_lossy(&email.formatted()));
+            }
+            println!("Test email sent to {}", to);
+        }
+    }
+    Ok(())
+}
+
+fn read_password(password: Option<String>) -> Result<String, Error> {
+    if let Some(password) = password {
+        return Ok(password);
+    }
+    eprintln!("Password:");
+    let mut line = String::new();
+    std::io::stdin().lock().read_line(&mut line)?;
+    let password = line.trim_end_matches(['\r', '\n']).to_string();
+    if password.is_empty() {
+        return Err(Error::PasswordEmpty);
+    }
+    Ok(password)
+}
@@ -0,0 +1,806 @@
+use crate::errors::Error;
+use crate::jwt::JwtKeys;
+use crate::signature::TrustedKey;
+use crate::wasm::WasmLimits;
+use crate::DbType;
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{
+    fmt::Display,
+    fs::{self, OpenOptions},
+    io::Write,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use toml_edit::{value, DocumentMut, Item, Table};
+
+pub const CONFIG_FILE: &str = "config.toml";
+const DATABASE_SECRET_FILE: &str = ".secrets/database_password";
+
+/// Every section is optional and falls back to its defaults, and keys that
+/// lucle does not know about (the mail server sections) are ignored.
+#[derive(Debug, Default)]
+pub struct LucleConfig {
+    /// File the configuration was loaded from, written back by the install wizard
+    pub path: PathBuf,
+    pub database: DatabaseConfig,
+    pub listeners: ListenersConfig,
+    pub tls: TlsConfig,
+    pub jwt: JwtConfig,
+    pub mail: MailConfig,
+    pub paths: PathsConfig,
+    pub upload: UploadConfig,
+    pub plugins: PluginsConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenersConfig {
+    pub grpc: SocketAddr,
+    pub http: SocketAddr,
+    /// URL users reach the web interface at, used to build links in emails
+    pub public_url: String,
+}
+
+impl Default for ListenersConfig {
+    fn default() -> Self {
+        Self {
+            grpc: SocketAddr::from(([127, 0, 0, 1], 3000)),
+            http: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            public_url: "http://127.0.0.1:8080".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// Holds `ca_cert.pem`, `server_cert.pem` and `server_private_key.pem`,
+    /// generated on first start when missing
+    pub directory: PathBuf,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from(".tls"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JwtConfig {
+    /// Base64 encoded PKCS#8 ES256 key signing new tokens
+    pub private_key: PathBuf,
+    /// Keys rotated out of `private_key`, still accepted to verify the
+    /// tokens they signed until those expire
+    pub previous_keys: Vec<PathBuf>,
+    /// Lifetimes in seconds
+    pub access_token_lifetime: u64,
+    pub refresh_token_lifetime: u64,
+    pub reset_token_lifetime: u64,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        Self {
+            private_key: PathBuf::from("pkey"),
+            previous_keys: Vec::new(),
+            access_token_lifetime: 15 * 60,
+            refresh_token_lifetime: 30 * 24 * 60 * 60,
+            reset_token_lifetime: 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailConfig {
+    /// Sender of every email
+    pub from: String,
+    /// Templates replacing the built-in ones, laid out as
+    /// `<locale>/<name>.subject`, `.txt` and `.html`
+    pub templates: PathBuf,
+    /// Locale of the emails sent to users who did not choose one
+    pub default_locale: String,
+    pub transport: MailTransport,
+    /// Directory the file transport writes `.eml` files to
+    pub spool: PathBuf,
+    pub smtp: SmtpConfig,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            from: "Lucle <noreply@localhost>".to_string(),
+            templates: PathBuf::from("templates"),
+            default_locale: "en".to_string(),
+            transport: MailTransport::File,
+            spool: PathBuf::from("./"),
+            smtp: SmtpConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransport {
+    /// Relay through `[mail.smtp]`
+    Smtp,
+    /// Write every email to the spool directory
+    File,
+    /// Keep emails in memory, to inspect what would be sent
+    Memory,
+}
+
+impl FromStr for MailTransport {
+    type Err = String;
+
+    fn from_str(transport: &str) -> Result<Self, Self::Err> {
+        match transport {
+            "smtp" => Ok(MailTransport::Smtp),
+            "file" => Ok(MailTransport::File),
+            "memory" => Ok(MailTransport::Memory),
+            _ => Err(format!(
+                "unknown transport `{}`, expected smtp, file or memory",
+                transport
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmtpConfig {
+    pub host: String,
+    /// Defaults to 587 with STARTTLS, 465 with implicit TLS and 25 without TLS
+    pub port: Option<u16>,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    /// Plain value or `%{file:<path>}%` / `%{env:<name>}%` reference
+    pub password: Option<String>,
+    /// Maximum number of pooled connections
+    pub pool_size: u32,
+    /// Timeout of SMTP commands in seconds
+    pub timeout: u64,
+}
+
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_string(),
+            port: None,
+            security: SmtpSecurity::Starttls,
+            username: None,
+            password: None,
+            pool_size: 10,
+            timeout: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Upgrade a plain connection, usually on port 587
+    Starttls,
+    /// TLS from the start of the connection, usually on port 465
+    Tls,
+    /// No encryption, only for a relay on a trusted network
+    None,
+}
+
+impl FromStr for SmtpSecurity {
+    type Err = String;
+
+    fn from_str(security: &str) -> Result<Self, Self::Err> {
+        match security {
+            "starttls" => Ok(SmtpSecurity::Starttls),
+            "tls" => Ok(SmtpSecurity::Tls),
+            "none" => Ok(SmtpSecurity::None),
+            _ => Err(format!(
+                "unknown security `{}`, expected starttls, tls or none",
+                security
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    /// Built web interface served by the HTTP listener
+    pub web: PathBuf,
+    /// Root of the update repositories, one directory per repository
+    pub repositories: PathBuf,
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        Self {
+            web: PathBuf::from("web/dist"),
+            repositories: PathBuf::from("repositories"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadConfig {
+    /// Largest binary accepted by the Repo upload, in bytes
+    pub max_file_size: u64,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            max_file_size: 4 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PluginsConfig {
+    /// Scanned at startup, one subdirectory with a `plugin.toml` per plugin
+    pub directory: PathBuf,
+    /// Minisign public keys allowed to sign plugin libraries
+    pub trusted_keys: Vec<String>,
+    /// Largest plugin installed from an archive, in bytes, counting both the
+    /// archive and its unpacked files
+    pub max_size: u64,
+    /// Fuel of each call into a WebAssembly plugin, about one per
+    /// instruction
+    pub wasm_fuel: u64,
+    /// Largest memory of a WebAssembly plugin, in bytes
+    pub wasm_max_memory: usize,
+}
+
+impl Default for PluginsConfig {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("plugins"),
+            trusted_keys: Vec::new(),
+            max_size: 256 * 1024 * 1024,
+            wasm_fuel: 100_000_000,
+            wasm_max_memory: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl PluginsConfig {
+    pub fn trusted_keys(&self) -> Result<Vec<TrustedKey>, Error> {
+        self.trusted_keys
+            .iter()
+            .map(|key| TrustedKey::parse(key))
+            .collect()
+    }
+
+    pub fn wasm_limits(&self) -> WasmLimits {
+        WasmLimits {
+            fuel: self.wasm_fuel,
+            max_memory: self.wasm_max_memory,
+        }
+    }
+}
+
+impl LucleConfig {
+    /// Load the configuration, apply `LUCLE_<SECTION>_<KEY>` environment
+    /// overrides and validate it. Every problem found is returned, prefixed
+    /// with the file and key it comes from.
+    pub fn load(path: &Path) -> Result<Self, Vec<String>> {
+        Self::read(path, true)
+    }
+
+    /// Like `load`, without reading the JWT signing keys, for commands that
+    /// do not sign tokens and may run before the keys exist.
+    pub fn load_without_jwt_keys(path: &Path) -> Result<Self, Vec<String>> {
+        Self::read(path, false)
+    }
+
+    fn read(path: &Path, jwt_keys: bool) -> Result<Self, Vec<String>> {
+        let file = path.display();
+        let table = match fs::read_to_string(path) {
+            Ok(content) => content
+                .parse::<toml::Table>()
+                .map_err(|err| vec![format!("{}: {}", file, err)])?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                tracing::warn!("{} not found, using default configuration", file);
+                toml::Table::new()
+            }
+            Err(err) => return Err(vec![format!("{}: {}", file, err)]),
+        };
+
+        let mut errors = Vec::new();
+        let mut config = LucleConfig {
+            path: path.to_path_buf(),
+            database: section(&table, "database", path, &mut errors),
+            listeners: section(&table, "listeners", path, &mut errors),
+            tls: section(&table, "tls", path, &mut errors),
+            jwt: section(&table, "jwt", path, &mut errors),
+            mail: section(&table, "mail", path, &mut errors),
+            paths: section(&table, "paths", path, &mut errors),
+            upload: section(&table, "upload", path, &mut errors),
+            plugins: section(&table, "plugins", path, &mut errors),
+        };
+        config.apply_env(&mut errors);
+        config.anchor_paths();
+        config.validate(jwt_keys, &mut errors);
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn apply_env(&mut self, errors: &mut Vec<String>) {
+        let database = &mut self.database;
+        env_override("LUCLE_DATABASE_DATABASE", &mut database.database, errors);
+        env_override_opt("LUCLE_DATABASE_URL", &mut database.url, errors);
+        env_override_opt("LUCLE_DATABASE_HOSTNAME", &mut database.hostname, errors);
+        env_override_opt("LUCLE_DATABASE_PORT", &mut database.port, errors);
+        env_override_opt("LUCLE_DATABASE_NAME", &mut database.name, errors);
+        env_override_opt("LUCLE_DATABASE_USERNAME", &mut database.username, errors);
+        env_override_opt("LUCLE_DATABASE_PASSWORD", &mut database.password, errors);
+        env_override("LUCLE_LISTENERS_GRPC", &mut self.listeners.grpc, errors);
+        env_override("LUCLE_LISTENERS_HTTP", &mut self.listeners.http, errors);
+        env_override(
+            "LUCLE_LISTENERS_PUBLIC_URL",
+            &mut self.listeners.public_url,
+            errors,
+        );
+        env_override("LUCLE_TLS_DIRECTORY", &mut self.tls.directory, errors);
+        let jwt = &mut self.jwt;
+        env_override("LUCLE_JWT_PRIVATE_KEY", &mut jwt.private_key, errors);
+        env_override_list("LUCLE_JWT_PREVIOUS_KEYS", &mut jwt.previous_keys, errors);
+        env_override(
+            "LUCLE_JWT_ACCESS_TOKEN_LIFETIME",
+            &mut jwt.access_token_lifetime,
+            errors,
+        );
+        env_override(
+            "LUCLE_JWT_REFRESH_TOKEN_LIFETIME",
+            &mut jwt.refresh_token_lifetime,
+            errors,
+        );
+        env_override(
+            "LUCLE_JWT_RESET_TOKEN_LIFETIME",
+            &mut jwt.reset_token_lifetime,
+            errors,
+        );
+        env_override("LUCLE_MAIL_FROM", &mut self.mail.from, errors);
+        env_override("LUCLE_MAIL_TEMPLATES", &mut self.mail.templates, errors);
+        env_override(
+            "LUCLE_MAIL_DEFAULT_LOCALE",
+            &mut self.mail.default_locale,
+            errors,
+        );
+        env_override("LUCLE_MAIL_TRANSPORT", &mut self.mail.transport, errors);
+        env_override("LUCLE_MAIL_SPOOL", &mut self.mail.spool, errors);
+        let smtp = &mut self.mail.smtp;
+        env_override("LUCLE_MAIL_SMTP_HOST", &mut smtp.host, errors);
+        env_override_opt("LUCLE_MAIL_SMTP_PORT", &mut smtp.port, errors);
+        env_override("LUCLE_MAIL_SMTP_SECURITY", &mut smtp.security, errors);
+        env_override_opt("LUCLE_MAIL_SMTP_USERNAME", &mut smtp.username, errors);
+        env_override_opt("LUCLE_MAIL_SMTP_PASSWORD", &mut smtp.password, errors);
+        env_override("LUCLE_MAIL_SMTP_POOL_SIZE", &mut smtp.pool_size, errors);
+        env_override("LUCLE_MAIL_SMTP_TIMEOUT", &mut smtp.timeout, errors);
+        env_override("LUCLE_PATHS_WEB", &mut self.paths.web, errors);
+        env_override(
+            "LUCLE_PATHS_REPOSITORIES",
+            &mut self.paths.repositories,
+            errors,
+        );
+        env_override(
+            "LUCLE_UPLOAD_MAX_FILE_SIZE",
+            &mut self.upload.max_file_size,
+            errors,
+        );
+        env_override(
+            "LUCLE_PLUGINS_DIRECTORY",
+            &mut self.plugins.directory,
+            errors,
+        );
+        env_override_list(
+            "LUCLE_PLUGINS_TRUSTED_KEYS",
+            &mut self.plugins.trusted_keys,
+            errors,
+        );
+        env_override("LUCLE_PLUGINS_MAX_SIZE", &mut self.plugins.max_size, errors);
+        env_override(
+            "LUCLE_PLUGINS_WASM_FUEL",
+            &mut self.plugins.wasm_fuel,
+            errors,
+        );
+        env_override(
+            "LUCLE_PLUGINS_WASM_MAX_MEMORY",
+            &mut self.plugins.wasm_max_memory,
+            errors,
+        );
+    }
+
+    /// Directory of the configuration file, relative paths start from it
+    pub fn dir(&self) -> &Path {
+        match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        }
+    }
+
+    /// Make relative paths and `%{file:<path>}%` references start from the
+    /// directory of the configuration file instead of the working directory
+    fn anchor_paths(&mut self) {
+        let dir = self.dir().to_path_buf();
+        let paths = [
+            &mut self.tls.directory,
+            &mut self.jwt.private_key,
+            &mut self.mail.templates,
+            &mut self.mail.spool,
+            &mut self.paths.web,
+            &mut self.paths.repositories,
+            &mut self.plugins.directory,
+        ];
+        for path in paths.into_iter().chain(&mut self.jwt.previous_keys) {
+            *path = dir.join(&*path);
+        }
+        // Also when the file is named after the database
+        if let Ok(DbType::Sqlite(file) | DbType::Surrealdb(file)) = self.database.db_type() {
+            if Path::new(&file).is_relative() && !file.contains(':') {
+                self.database.url = Some(dir.join(file).display().to_string());
+            }
+        }
+        let secrets = [&mut self.database.password, &mut self.mail.smtp.password];
+        for secret in secrets.into_iter().flatten() {
+            *secret = anchor_secret(secret, &dir);
+        }
+    }
+
+    fn validate(&self, jwt_keys: bool, errors: &mut Vec<String>) {
+        let file = self.path.display();
+        let database = &self.database;
+        match database.database.as_str() {
+            "" | "sqlite" | "surrealdb" => {}
+            "mysql" | "postgresql" => {
+                if database.url.is_none()
+                    && (database.hostname.is_none() || database.port.is_none())
+                {
+                    errors.push(format!(
+                        "{}: [database] url: required when hostname and port are not set",
+                        file
+                    ));
+                }
+            }
+            other => errors.push(format!(
+                "{}: [database] database: unknown backend `{}`, expected mysql, postgresql, sqlite or surrealdb",
+                file, other
+            )),
+        }
+        if let Some(password) = &database.password {
+            if let Err(err) = resolve_secret(password) {
+                errors.push(format!("{}: [database] password: {}", file, err));
+            }
+        }
+        if self.listeners.grpc == self.listeners.http {
+            errors.push(format!(
+                "{}: [listeners] http: {} is already used by grpc",
+                file, self.listeners.http
+            ));
+        }
+        match url::Url::parse(&self.listeners.public_url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+            Ok(_) => errors.push(format!(
+                "{}: [listeners] public_url: expected an http or https url",
+                file
+            )),
+            Err(err) => errors.push(format!("{}: [listeners] public_url: {}", file, err)),
+        }
+        if let Err(err) = self.mail.from.parse::<lettre::message::Mailbox>() {
+            errors.push(format!("{}: [mail] from: {}", file, err));
+        }
+        match self.mail.transport {
+            MailTransport::Smtp => {
+                let smtp = &self.mail.smtp;
+                if smtp.host.is_empty() {
+                    errors.push(format!("{}: [mail.smtp] host: required", file));
+                }
+                if smtp.username.is_some() != smtp.password.is_some() {
+                    errors.push(format!(
+                        "{}: [mail.smtp] username and password must be set together",
+                        file
+                    ));
+                }
+                if let Some(password) = &smtp.password {
+                    if let Err(err) = resolve_secret(password) {
+                        errors.push(format!("{}: [mail.smtp] password: {}", file, err));
+                    }
+                }
+                if smtp.pool_size == 0 {
+                    errors.push(format!(
+                        "{}: [mail.smtp] pool_size: must be greater than 0",
+                        file
+                    ));
+                }
+            }
+            MailTransport::File if !self.mail.spool.is_dir() => errors.push(format!(
+                "{}: [mail] spool: {} does not exist",
+                file,
+                self.mail.spool.display()
+            )),
+            MailTransport::File | MailTransport::Memory => {}
+        }
+        if jwt_keys {
+            if let Err(err) = JwtKeys::load(&self.jwt) {
+                errors.push(format!("{}: [jwt] {}", file, err));
+            }
+        }
+        for (key, lifetime) in [
+            ("access_token_lifetime", self.jwt.access_token_lifetime),
+            ("refresh_token_lifetime", self.jwt.refresh_token_lifetime),
+            ("reset_token_lifetime", self.jwt.reset_token_lifetime),
+        ] {
+            if lifetime == 0 {
+                errors.push(format!("{}: [jwt] {}: must be greater than 0", file, key));
+            }
+        }
+        if let Err(err) = self.plugins.trusted_keys() {
+            errors.push(format!("{}: [plugins] trusted_keys: {}", file, err));
+        }
+        for (key, value) in [
+            ("max_size", self.plugins.max_size),
+            ("wasm_fuel", self.plugins.wasm_fuel),
+            ("wasm_max_memory", self.plugins.wasm_max_memory as u64),
+        ] {
+            if value == 0 {
+                errors.push(format!(
+                    "{}: [plugins] {}: must be greater than 0",
+                    file, key
+                ));
+            }
+        }
+        if self.upload.max_file_size == 0 {
+            errors.push(format!(
+                "{}: [upload] max_file_size: must be greater than 0",
+                file
+            ));
+        }
+        if !self.paths.web.is_dir() {
+            tracing::warn!(
+                "{}: [paths] web: {} does not exist, the web interface will not be served",
+                file,
+                self.paths.web.display()
+            );
+        }
+    }
+}
+
+fn section<T: DeserializeOwned + Default>(
+    table: &toml::Table,
+    name: &str,
+    path: &Path,
+    errors: &mut Vec<String>,
+) -> T {
+    match table.get(name) {
+        Some(section) => section.clone().try_into().unwrap_or_else(|err| {
+            let err = err.to_string().trim_end().replace('\n', " ");
+            errors.push(format!("{}: [{}] {}", path.display(), name, err));
+            T::default()
+        }),
+        None => T::default(),
+    }
+}
+
+fn env_override<T: FromStr>(name: &str, target: &mut T, errors: &mut Vec<String>)
+where
+    T::Err: Display,
+{
+    if let Ok(value) = std::env::var(name) {
+        match value.parse() {
+            Ok(value) => *target = value,
+            Err(err) => errors.push(format!("environment variable {}: {}", name, err)),
+        }
+    }
+}
+
+/// Lists are comma separated, an empty variable clears the list
+fn env_override_list<T: FromStr>(name: &str, target: &mut Vec<T>, errors: &mut Vec<String>)
+where
+    T::Err: Display,
+{
+    if let Ok(value) = std::env::var(name) {
+        match value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::parse)
+            .collect()
+        {
+            Ok(value) => *target = value,
+            Err(err) => errors.push(format!("environment variable {}: {}", name, err)),
+        }
+    }
+}
+
+fn env_override_opt<T: FromStr>(name: &str, target: &mut Option<T>, errors: &mut Vec<String>)
+where
+    T::Err: Display,
+{
+    if let Ok(value) = std::env::var(name) {
+        match value.parse() {
+            Ok(value) => *target = Some(value),
+            Err(err) => errors.push(format!("environment variable {}: {}", name, err)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub database: String,
+    pub url: Option<String>,
+    pub hostname: Option<String>,
+    pub port: Option<String>,
+    pub name: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl DatabaseConfig {
+    pub fn db_type(&self) -> Result<DbType, Error> {
+        let db_name = self.name.clone().unwrap_or("lucle".to_string());
+        match self.database.as_str() {
+            "mysql" => Ok(DbType::Mysql(self.server_url("mysql", &db_name)?)),
+            "postgresql" => Ok(DbType::Postgresql(self.server_url("postgres", &db_name)?)),
+            "sqlite" => Ok(DbType::Sqlite(self.url.clone().unwrap_or(db_name + ".db"))),
+            "surrealdb" => Ok(DbType::Surrealdb(self.url.clone().unwrap_or(db_name))),
+            _ => Ok(DbType::NoDatabase),
+        }
+    }
+
+    /// Use `url` when it is set, otherwise build one from the connection fields.
+    fn server_url(&self, scheme: &str, db_name: &str) -> Result<String, Error> {
+        if let Some(url) = &self.url {
+            return Ok(url.clone());
+        }
+        let (Some(hostname), Some(port)) = (&self.hostname, &self.port) else {
+            return Err(Error::Config(format!(
+                "missing url or hostname/port for {} database",
+                self.database
+            )));
+        };
+        let mut url = url::Url::parse(&format!("{}://{}:{}/{}", scheme, hostname, port, db_name))?;
+        // Credentials are percent-encoded by the setters
+        if let Some(username) = &self.username {
+            let _ = url.set_username(username);
+        }
+        if let Some(password) = &self.password {
+            let _ = url.set_password(Some(&resolve_secret(password)?));
+        }
+        Ok(url.into())
+    }
+
+    /// Move the password to a secret file next to the configuration file in
+    /// `dir` and keep only a reference to it, so that the configuration file
+    /// never holds the credential itself.
+    pub fn store_password(&mut self, dir: &Path) -> Result<(), Error> {
+        if let Some(password) = &self.password {
+            store_secret(&dir.join(DATABASE_SECRET_FILE), password)?;
+            self.password = Some(format!("%{{file:{}}}%", DATABASE_SECRET_FILE));
+        }
+        Ok(())
+    }
+
+    /// Write the `[database]` section of the configuration file, keeping every
+    /// other section and comment as is.
+    pub fn save(&self, config_file: &Path) -> Result<(), Error> {
+        let content = match fs::read_to_string(config_file) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(Error::Io(err)),
+        };
+        let mut doc = content.parse::<DocumentMut>()?;
+        if !doc.contains_table("database") {
+            doc.insert("database", Item::Table(Table::new()));
+        }
+        let table = doc["database"].as_table_mut().unwrap();
+        table.clear();
+        table["database"] = value(self.database.as_str());
+        let fields = [
+            ("url", &self.url),
+            ("hostname", &self.hostname),
+            ("port", &self.port),
+            ("name", &self.name),
+            ("username", &self.username),
+        ];
+        for (key, field) in fields {
+            if let Some(field) = field {
+                table[key] = value(field.as_str());
+            }
+        }
+        if let Some(password) = &self.password {
+            table["password"] = value(password.as_str());
+        }
+        fs::write(config_file, doc.to_string())?;
+        Ok(())
+    }
+}
+
+/// Resolve `%{file:<path>}%` and `%{env:<name>}%` references, the same macros
+/// the mail server configuration uses. Any other value is returned as is.
+pub fn resolve_secret(secret: &str) -> Result<String, Error> {
+    let Some(reference) = secret
+        .strip_prefix("%{")
+        .and_then(|secret| secret.strip_suffix("}%"))
+    else {
+        return Ok(secret.to_string());
+    };
+    match reference.split_once(':') {
+        Some(("file", path)) => Ok(fs::read_to_string(path)?
+            .trim_end_matches(['\r', '\n'])
+            .to_string()),
+        Some(("env", name)) => std::env::var(name)
+            .map_err(|err| Error::Config(format!("unable to read secret {}: {}", name, err))),
+        _ => Err(Error::Config(format!(
+            "unknown secret reference {}",
+            secret
+        ))),
+    }
+}
+
+/// Prefix the path of a relative `%{file:<path>}%` reference with `dir`
+fn anchor_secret(secret: &str, dir: &Path) -> String {
+    match secret
+        .strip_prefix("%{file:")
+        .and_then(|secret| secret.strip_suffix("}%"))
+    {
+        Some(path) if Path::new(path).is_relative() => {
+            format!("%{{file:{}}}%", dir.join(path).display())
+        }
+        _ => secret.to_string(),
+    }
+}
+
+fn store_secret(path: &Path, secret: &str) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    file.write_all(secret.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_paths_start_from_the_config_directory() {
+        let dir = std::env::temp_dir().join(format!("lucle-config-paths-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        crate::jwt::generate_key(&dir.join("pkey")).unwrap();
+        fs::write(dir.join("secret"), "password").unwrap();
+        let path = dir.join(CONFIG_FILE);
+        fs::write(
+            &path,
+            "[database]\ndatabase = \"sqlite\"\npassword = \"%{file:secret}%\"\n\
+             [mail]\ntransport = \"memory\"\n[plugins]\ndirectory = \"/srv/plugins\"\n",
+        )
+        .unwrap();
+
+        let config = LucleConfig::load(&path).unwrap();
+        assert_eq!(config.tls.directory, dir.join(".tls"));
+        assert_eq!(config.jwt.private_key, dir.join("pkey"));
+        assert_eq!(config.paths.repositories, dir.join("repositories"));
+        assert_eq!(config.plugins.directory, Path::new("/srv/plugins"));
+        let database = dir.join("lucle.db").display().to_string();
+        assert_eq!(config.database.url, Some(database));
+        let secret = format!("%{{file:{}}}%", dir.join("secret").display());
+        assert_eq!(config.database.password, Some(secret));
+    }
+}
@@ -13,14 +13,197 @@ pub enum Error {
     UserNotFound,
     #[error("No user created")]
     UserNotCreated,
-    #[error("Email not found")]
-    EmailNotFound,
     #[error("Email not valid")]
     EmailNotValid,
+    #[error("Unknown role `{0}`, expected admin, maintainer or member")]
+    InvalidRole(String),
+    #[error("Permission denied")]
+    PermissionDenied,
+    #[error("Unknown permission `{0}`, expected read or write")]
+    InvalidPermission(String),
+    #[error("{0} is not a member of this repository and has no pending request")]
+    MemberNotFound(String),
+    #[error("Already a member of {0} or waiting to join it")]
+    AlreadyMember(String),
+    #[error("A repository needs at least one member with write access")]
+    LastWriter,
+    #[error("Repository {0} not found")]
+    RepositoryNotFound(String),
+    #[error("Repository {0} already exists")]
+    RepositoryExists(String),
+    #[error("Invalid repository name `{0}`, expected letters, digits, `-`, `_` or `.`")]
+    InvalidRepositoryName(String),
+    #[error("Repository {0} is not initialized")]
+    RepositoryNotInitialized(String),
+    #[error("Repository {0} is already initialized")]
+    RepositoryAlreadyInitialized(String),
+    #[error("Version cannot be empty")]
+    VersionEmpty,
+    #[error("Version {0} not found")]
+    VersionNotFound(String),
+    #[error("Version {0} already exists")]
+    VersionExists(String),
+    #[error("Version {0} is the current version or the target of a registered package")]
+    VersionInUse(String),
+    #[error("Package {0} not found")]
+    PackageNotFound(String),
+    #[error("Package {0} is already registered")]
+    PackageExists(String),
+    #[error("Package {0} data is missing or does not match its metadata")]
+    PackageCorrupted(String),
+    #[error("Package {0} is registered, unregister it first")]
+    PackageRegistered(String),
+    #[error("Invalid minisign public key `{0}`")]
+    InvalidPublicKey(String),
+    #[error("Trusted key {0} not found")]
+    TrustedKeyNotFound(String),
+    #[error("Signature {0}.minisig is missing")]
+    SignatureMissing(String),
+    #[error("Signature of {0} is invalid: {1}")]
+    SignatureInvalid(String, String),
+    #[error("{0} is not signed by a trusted key")]
+    UntrustedSignature(String),
+    #[error("Invalid plugin manifest {0}: {1}")]
+    PluginManifest(String, String),
+    #[error("Plugin ABI version {0} is not supported, expected {1}")]
+    PluginAbiVersion(u32, u32),
+    #[error("Plugin error: {0}")]
+    Plugin(String),
+    #[error("Plugin {0} not found")]
+    PluginNotFound(String),
+    #[error("Plugin {0} is already installed")]
+    PluginExists(String),
+    #[error("Invalid plugin archive: {0}")]
+    PluginArchive(String),
+    #[error("Page {0} not found")]
+    PageNotFound(String),
+    #[error("Revision {1} of page {0} not found")]
+    PageRevisionNotFound(String, i32),
+    #[error("Page {0} already exists")]
+    PageExists(String),
+    #[error("Page {0} keeps being saved by other editors, try again")]
+    PageChanged(String),
+    #[error(
+        "Invalid page slug `{0}`, expected `/` separated lowercase letters, digits, `-` or `_`"
+    )]
+    InvalidSlug(String),
+    #[error("Unknown page status `{0}`, expected draft or published")]
+    InvalidPageStatus(String),
+    #[error("Invalid file name `{0}`")]
+    InvalidFileName(String),
+    #[error("Binary {0} already exists, delete it first")]
+    BinaryExists(String),
+    #[error("Upload of {0} is larger than {1} bytes")]
+    UploadTooLarge(String, u64),
+    #[error("Upload of {0} must resume at byte {1}")]
+    UploadOffset(String, u64),
+    #[error("{0} is already being uploaded")]
+    UploadInProgress(String),
+    #[error("Invalid SHA-256 `{0}`, expected 64 hexadecimal digits")]
+    InvalidChecksum(String),
+    #[error("Upload of {0} does not match its SHA-256 and was dropped")]
+    ChecksumMismatch(String),
+    #[error("Invalid repository file: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Password cannot be empty")]
+    PasswordEmpty,
     #[error("Failed to hash password: {0}")]
     Argon2(#[from] argon2::password_hash::Error),
     #[error("Failed to create database connection")]
     Deadpool(#[from] diesel_async::pooled_connection::deadpool::PoolError),
-    #[error("Failed to parse database url: {0}")]
+    #[error("Failed to build database pool: {0}")]
+    PoolBuild(#[from] diesel_async::pooled_connection::deadpool::BuildError),
+    #[error("SurrealDB error: {0}")]
+    Surrealdb(Box<surrealdb::Error>),
+    #[error("Invalid token: {0}")]
+    Jwt(#[from] jsonwebtoken::errors::Error),
+    #[error("Token has been revoked")]
+    TokenRevoked,
+    #[error("{0}")]
+    Unauthenticated(&'static str),
+    #[error("Invalid email address: {0}")]
+    MailAddress(#[from] lettre::address::AddressError),
+    #[error("Failed to build email: {0}")]
+    MailBuild(#[from] lettre::error::Error),
+    #[error("Failed to send email: {0}")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("Failed to write email to the spool: {0}")]
+    MailSpool(#[from] lettre::transport::file::Error),
+    #[error("Failed to render template: {0}")]
+    Template(#[from] tera::Error),
+    #[error("No database configured")]
+    NoDatabase,
+    #[error("Invalid database name `{0}`, expected letters, digits, `-`, `_` or `.`")]
+    InvalidDatabaseName(String),
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+    #[error("Failed to edit configuration: {0}")]
+    ConfigEdit(#[from] toml_edit::TomlError),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse url: {0}")]
     UrlParsing(#[from] url::ParseError),
 }
+
+impl From<surrealdb::Error> for Error {
+    fn from(err: surrealdb::Error) -> Self {
+        Error::Surrealdb(Box::new(err))
+    }
+}
+
+impl From<Error> for tonic::Status {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::NoDatabase => tonic::Status::failed_precondition(err.to_string()),
+            Error::Config(_)
+            | Error::InvalidDatabaseName(_)
+            | Error::InvalidRole(_)
+            | Error::InvalidPermission(_)
+            | Error::InvalidRepositoryName(_)
+            | Error::InvalidFileName(_)
+            | Error::VersionEmpty
+            | Error::InvalidChecksum(_)
+            | Error::InvalidPublicKey(_)
+            | Error::ChecksumMismatch(_)
+            | Error::PluginManifest(..)
+            | Error::PluginArchive(_)
+            | Error::InvalidSlug(_)
+            | Error::InvalidPageStatus(_) => tonic::Status::invalid_argument(err.to_string()),
+            Error::MemberNotFound(_)
+            | Error::RepositoryNotFound(_)
+            | Error::VersionNotFound(_)
+            | Error::PackageNotFound(_)
+            | Error::TrustedKeyNotFound(_)
+            | Error::PluginNotFound(_)
+            | Error::PageNotFound(_)
+            | Error::PageRevisionNotFound(..) => tonic::Status::not_found(err.to_string()),
+            Error::RepositoryExists(_)
+            | Error::AlreadyMember(_)
+            | Error::RepositoryAlreadyInitialized(_)
+            | Error::VersionExists(_)
+            | Error::PackageExists(_)
+            | Error::BinaryExists(_)
+            | Error::UploadInProgress(_)
+            | Error::PluginExists(_)
+            | Error::PageExists(_) => tonic::Status::already_exists(err.to_string()),
+            Error::LastWriter
+            | Error::RepositoryNotInitialized(_)
+            | Error::VersionInUse(_)
+            | Error::PackageRegistered(_)
+            | Error::PackageCorrupted(_)
+            | Error::SignatureMissing(_)
+            | Error::SignatureInvalid(..)
+            | Error::UntrustedSignature(_)
+            | Error::PluginAbiVersion(..) => tonic::Status::failed_precondition(err.to_string()),
+            Error::PageChanged(_) => tonic::Status::aborted(err.to_string()),
+            Error::UploadTooLarge(..) | Error::UploadOffset(..) => {
+                tonic::Status::out_of_range(err.to_string())
+            }
+            Error::PermissionDenied => tonic::Status::permission_denied(err.to_string()),
+            Error::Jwt(_) | Error::TokenRevoked | Error::Unauthenticated(_) => {
+                tonic::Status::unauthenticated(err.to_string())
+            }
+            _ => tonic::Status::internal(err.to_string()),
+        }
+    }
+}
@@ -1,22 +1,76 @@
-use axum::Router;
-use std::net::SocketAddr;
+use crate::page;
+use crate::plugin::{self, PluginHost};
+use crate::storage::SharedStorage;
+use axum::{
+    extract::Request,
+    http::{header, Method, StatusCode},
+    response::{Html, IntoResponse, Response},
+    routing::any,
+    Router,
+};
+use std::{convert::Infallible, net::SocketAddr, path::Path, sync::Arc};
+use tokio::sync::watch;
+use tower::ServiceExt;
 use tower_http::{
     services::{ServeDir, ServeFile},
     trace::TraceLayer,
 };
 
-pub async fn serve_dir() {
-    let addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+pub async fn serve_dir(
+    addr: SocketAddr,
+    web: &Path,
+    storage: SharedStorage,
+    plugins: Arc<PluginHost>,
+    shutdown: watch::Receiver<bool>,
+) {
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
     let local_addr = listener.local_addr().unwrap();
     tracing::info!("HTTP listening on {local_addr}");
 
-    let serve_dir = ServeDir::new("web/dist").fallback(ServeFile::new("web/dist/index.html"));
+    // Paths without a static file are published pages, or routes of the web app
+    let index = ServeFile::new(web.join("index.html"));
+    let pages =
+        tower::service_fn(move |request| page_or_index(storage.clone(), index.clone(), request));
+    let serve_dir = ServeDir::new(web).fallback(pages);
 
     let app = Router::new()
+        .route(
+            "/plugins/:plugin/*path",
+            any(move |route, request| plugin::http_handler(plugins.clone(), route, request)),
+        )
         .nest_service("/", serve_dir.clone())
         .fallback_service(serve_dir)
         .layer(TraceLayer::new_for_http());
 
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(crate::utils::shutdown_requested(shutdown))
+        .await
+        .unwrap();
+}
+
+async fn page_or_index(
+    storage: SharedStorage,
+    index: ServeFile,
+    request: Request,
+) -> Result<Response, Infallible> {
+    let slug = request.uri().path().trim_matches('/');
+    let is_read = matches!(*request.method(), Method::GET | Method::HEAD);
+    // Before the install wizard creates the database there are no pages
+    if let (true, true, Ok(storage)) = (is_read, page::is_valid_slug(slug), storage.get()) {
+        match page::render_published(&*storage, slug).await {
+            Ok(Some(html)) => {
+                let csp = [(
+                    header::CONTENT_SECURITY_POLICY,
+                    page::CONTENT_SECURITY_POLICY,
+                )];
+                return Ok((csp, Html(html)).into_response());
+            }
+            Ok(None) => {}
+            Err(err) => {
+                tracing::error!("{}", err);
+                return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+            }
+        }
+    }
+    Ok(index.oneshot(request).await?.into_response())
 }
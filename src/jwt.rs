@@ -0,0 +1,227 @@
+use crate::config::JwtConfig;
+use crate::errors::Error;
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine as _,
+};
+use jsonwebtoken::{
+    decode, decode_header, encode, errors::ErrorKind, get_current_timestamp, Algorithm,
+    DecodingKey, EncodingKey, Header, Validation,
+};
+use ring::{
+    digest,
+    rand::{SecureRandom, SystemRandom},
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    Access,
+    Refresh,
+    PasswordReset,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub email: String,
+    pub iat: u64,
+    pub exp: u64,
+    pub jti: String,
+    pub scope: Scope,
+}
+
+/// Signing key and every key tokens are verified with, indexed by `kid`.
+/// The key id is derived from the public key so that a token names the key
+/// that signed it and keeps verifying after `private_key` is rotated.
+pub struct JwtKeys {
+    kid: String,
+    encoding_key: EncodingKey,
+    decoding_keys: HashMap<String, DecodingKey>,
+    access_token_lifetime: u64,
+    refresh_token_lifetime: u64,
+    reset_token_lifetime: u64,
+}
+
+impl JwtKeys {
+    pub fn load(config: &JwtConfig) -> Result<Self, Error> {
+        let (kid, encoding_key, decoding_key) = read_key(&config.private_key)?;
+        let mut decoding_keys = HashMap::from([(kid.clone(), decoding_key)]);
+        for path in &config.previous_keys {
+            let (kid, _, decoding_key) = read_key(path)?;
+            decoding_keys.insert(kid, decoding_key);
+        }
+        Ok(Self {
+            kid,
+            encoding_key,
+            decoding_keys,
+            access_token_lifetime: config.access_token_lifetime,
+            refresh_token_lifetime: config.refresh_token_lifetime,
+            reset_token_lifetime: config.reset_token_lifetime,
+        })
+    }
+
+    pub fn claims(&self, scope: Scope, username: String, email: String) -> Claims {
+        let lifetime = match scope {
+            Scope::Access => self.access_token_lifetime,
+            Scope::Refresh => self.refresh_token_lifetime,
+            Scope::PasswordReset => self.reset_token_lifetime,
+        };
+        let now = get_current_timestamp();
+        Claims {
+            sub: username,
+            email,
+            iat: now,
+            exp: now + lifetime,
+            jti: random_id(),
+            scope,
+        }
+    }
+
+    pub fn encode(&self, claims: &Claims) -> Result<String, Error> {
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(self.kid.clone());
+        Ok(encode(&header, claims, &self.encoding_key)?)
+    }
+
+    /// Check the signature, expiry and scope of a token
+    pub fn verify(&self, token: &str, scope: Scope) -> Result<Claims, Error> {
+        let header = decode_header(token)?;
+        let decoding_key = header
+            .kid
+            .and_then(|kid| self.decoding_keys.get(&kid))
+            .ok_or_else(|| jsonwebtoken::errors::Error::from(ErrorKind::InvalidToken))?;
+        let mut validation = Validation::new(Algorithm::ES256);
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        let claims = decode::<Claims>(token, decoding_key, &validation)?.claims;
+        if claims.scope != scope {
+            return Err(jsonwebtoken::errors::Error::from(ErrorKind::InvalidToken).into());
+        }
+        Ok(claims)
+    }
+}
+
+fn read_key(path: &Path) -> Result<(String, EncodingKey, DecodingKey), Error> {
+    let invalid = |err: &dyn std::fmt::Display| {
+        Error::Config(format!("{}: invalid ES256 key: {}", path.display(), err))
+    };
+    let encoded = fs::read_to_string(path)
+        .map_err(|err| Error::Config(format!("{}: {}", path.display(), err)))?;
+    let pkcs8 = STANDARD
+        .decode(encoded.trim())
+        .map_err(|err| invalid(&err))?;
+    let key_pair = EcdsaKeyPair::from_pkcs8(
+        &ECDSA_P256_SHA256_FIXED_SIGNING,
+        &pkcs8,
+        &SystemRandom::new(),
+    )
+    .map_err(|err| invalid(&err))?;
+    let public_key = key_pair.public_key().as_ref();
+    let kid = URL_SAFE_NO_PAD.encode(&digest::digest(&digest::SHA256, public_key).as_ref()[..12]);
+    Ok((
+        kid,
+        EncodingKey::from_ec_der(&pkcs8),
+        DecodingKey::from_ec_der(public_key),
+    ))
+}
+
+/// Write a new base64 encoded PKCS#8 ES256 key readable by the owner only
+pub fn generate_key(path: &Path) -> Result<(), Error> {
+    let pkcs8 =
+        EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+            .map_err(|err| Error::Config(err.to_string()))?;
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    file.write_all(STANDARD.encode(pkcs8.as_ref()).as_bytes())?;
+    Ok(())
+}
+
+fn random_id() -> String {
+    let mut bytes = [0u8; 16];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("system random number generator");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// New key in the temporary directory, `name` is unique per test
+    pub fn generate_test_key(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("lucle-{}-{}.key", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        generate_key(&path).unwrap();
+        path
+    }
+
+    pub fn load_keys(private_key: &Path, previous_keys: &[PathBuf]) -> JwtKeys {
+        JwtKeys::load(&JwtConfig {
+            private_key: private_key.to_path_buf(),
+            previous_keys: previous_keys.to_vec(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn token(keys: &JwtKeys, scope: Scope) -> String {
+        let claims = keys.claims(scope, "alice".to_string(), "alice@example.com".to_string());
+        keys.encode(&claims).unwrap()
+    }
+
+    #[test]
+    fn previous_keys_verify_the_tokens_they_signed() {
+        let old_key = generate_test_key("jwt-rotation-old");
+        let new_key = generate_test_key("jwt-rotation-new");
+        let old_token = token(&load_keys(&old_key, &[]), Scope::Access);
+
+        let rotated = load_keys(&new_key, std::slice::from_ref(&old_key));
+        let claims = rotated.verify(&old_token, Scope::Access).unwrap();
+        assert_eq!(claims.sub, "alice");
+        let new_token = token(&rotated, Scope::Access);
+        assert_ne!(
+            decode_header(&new_token).unwrap().kid,
+            decode_header(&old_token).unwrap().kid
+        );
+        assert!(load_keys(&new_key, &[])
+            .verify(&new_token, Scope::Access)
+            .is_ok());
+
+        // Once the old key is dropped its tokens are refused
+        assert!(load_keys(&new_key, &[])
+            .verify(&old_token, Scope::Access)
+            .is_err());
+    }
+
+    #[test]
+    fn tokens_are_refused_for_another_scope() {
+        let keys = load_keys(&generate_test_key("jwt-scope"), &[]);
+        let refresh_token = token(&keys, Scope::Refresh);
+        assert!(keys.verify(&refresh_token, Scope::Refresh).is_ok());
+        assert!(keys.verify(&refresh_token, Scope::Access).is_err());
+        assert!(keys.verify(&refresh_token, Scope::PasswordReset).is_err());
+    }
+
+    #[test]
+    fn tokens_of_unknown_keys_are_refused() {
+        let keys = load_keys(&generate_test_key("jwt-unknown"), &[]);
+        let other = load_keys(&generate_test_key("jwt-unknown-other"), &[]);
+        assert!(keys
+            .verify(&token(&other, Scope::Access), Scope::Access)
+            .is_err());
+        assert!(keys.verify("not a token", Scope::Access).is_err());
+    }
+}
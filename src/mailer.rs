@@ -0,0 +1,211 @@
+use crate::config::{resolve_secret, MailConfig, MailTransport, SmtpConfig, SmtpSecurity};
+use crate::errors::Error;
+use lettre::{
+    message::{header, Mailbox, MultiPart, SinglePart},
+    transport::smtp::{authentication::Credentials, PoolConfig},
+    AsyncFileTransport, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+use tera::{Context, Tera};
+
+/// Every template exists as `<locale>/<name>.subject`, `.txt` and `.html`
+macro_rules! builtin_templates {
+    ($($locale:literal: [$($name:literal),*]),* $(,)?) => {
+        &[$($(
+            (
+                concat!($locale, "/", $name, ".subject"),
+                include_str!(concat!("templates/", $locale, "/", $name, ".subject")),
+            ),
+            (
+                concat!($locale, "/", $name, ".txt"),
+                include_str!(concat!("templates/", $locale, "/", $name, ".txt")),
+            ),
+            (
+                concat!($locale, "/", $name, ".html"),
+                include_str!(concat!("templates/", $locale, "/", $name, ".html")),
+            ),
+        )*)*]
+    };
+}
+
+const BUILTIN_TEMPLATES: &[(&str, &str)] = builtin_templates! {
+    "en": ["password_reset", "welcome", "join_request", "access_granted"],
+    "fr": ["password_reset", "welcome", "join_request", "access_granted"],
+};
+
+/// Emails lucle sends, with the context variables their templates use
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Template {
+    /// `username`, `link`, `expires_in` (minutes)
+    PasswordReset,
+    /// `username`, `link`
+    Welcome,
+    /// `username`, `requester`, `repository`, `link`
+    JoinRequest,
+    /// `username`, `repository`, `permission`, `link`
+    AccessGranted,
+}
+
+impl Template {
+    pub const ALL: [Template; 4] = [
+        Template::PasswordReset,
+        Template::Welcome,
+        Template::JoinRequest,
+        Template::AccessGranted,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Template::PasswordReset => "password_reset",
+            Template::Welcome => "welcome",
+            Template::JoinRequest => "join_request",
+            Template::AccessGranted => "access_granted",
+        }
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Template::ALL
+            .into_iter()
+            .find(|template| template.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<_> = Template::ALL.iter().map(Template::name).collect();
+                format!("unknown template `{}`, expected {}", name, names.join(", "))
+            })
+    }
+}
+
+enum Transport {
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+    File(AsyncFileTransport<Tokio1Executor>),
+    Memory(Mutex<Vec<Message>>),
+}
+
+/// Renders emails and delivers them with the transport selected in `[mail]`
+pub struct Mailer {
+    from: Mailbox,
+    tera: Tera,
+    default_locale: String,
+    transport: Transport,
+}
+
+impl Mailer {
+    pub fn new(config: &MailConfig) -> Result<Self, Error> {
+        let transport = match config.transport {
+            MailTransport::Smtp => Transport::Smtp(smtp_transport(&config.smtp)?),
+            MailTransport::File => Transport::File(AsyncFileTransport::new(&config.spool)),
+            MailTransport::Memory => Transport::Memory(Mutex::new(Vec::new())),
+        };
+        // Templates found in the configured directory replace the built-in ones
+        let mut builtin = Tera::default();
+        builtin.add_raw_templates(BUILTIN_TEMPLATES.to_vec())?;
+        let mut tera = Tera::new(&format!("{}/**/*", config.templates.display()))?;
+        tera.extend(&builtin)?;
+        Ok(Self {
+            from: config.from.parse()?,
+            tera,
+            default_locale: config.default_locale.clone(),
+            transport,
+        })
+    }
+
+    /// Render `template` in the recipient's `locale` and send it
+    pub async fn send(
+        &self,
+        template: Template,
+        dest: &str,
+        locale: &str,
+        context: &Context,
+    ) -> Result<(), Error> {
+        let subject = self.render(template, locale, "subject", context)?;
+        let text = self.render(template, locale, "txt", context)?;
+        let html = self.render(template, locale, "html", context)?;
+        let email = Message::builder()
+            .from(self.from.clone())
+            .to(dest.parse()?)
+            .subject(subject.trim())
+            .multipart(
+                MultiPart::alternative()
+                    .singlepart(
+                        SinglePart::builder()
+                            .header(header::ContentType::TEXT_PLAIN)
+                            .body(text), // Every message should have a plain text fallback.
+                    )
+                    .singlepart(
+                        SinglePart::builder()
+                            .header(header::ContentType::TEXT_HTML)
+                            .body(html),
+                    ),
+            )?;
+
+        match &self.transport {
+            Transport::Smtp(transport) => {
+                transport.send(email).await?;
+            }
+            Transport::File(transport) => {
+                transport.send(email).await?;
+            }
+            Transport::Memory(sent) => sent.lock().unwrap().push(email),
+        }
+        Ok(())
+    }
+
+    /// `fr-CA` falls back to `fr`, then to the default locale and to English
+    fn render(
+        &self,
+        template: Template,
+        locale: &str,
+        extension: &str,
+        context: &Context,
+    ) -> Result<String, Error> {
+        let language = locale.split(['-', '_']).next().unwrap_or_default();
+        for locale in [locale, language, &self.default_locale, "en"] {
+            let name = format!("{}/{}.{}", locale, template.name(), extension);
+            if self.tera.get_template_names().any(|known| known == name) {
+                return Ok(self.tera.render(&name, context)?);
+            }
+        }
+        Err(tera::Error::template_not_found(format!("{}.{}", template.name(), extension)).into())
+    }
+
+    /// Emails kept by the memory transport, oldest first
+    pub fn sent(&self) -> Vec<Message> {
+        match &self.transport {
+            Transport::Memory(sent) => sent.lock().unwrap().clone(),
+            Transport::Smtp(_) | Transport::File(_) => Vec::new(),
+        }
+    }
+}
+
+fn smtp_transport(config: &SmtpConfig) -> Result<AsyncSmtpTransport<Tokio1Executor>, Error> {
+    let (builder, port) = match config.security {
+        SmtpSecurity::Starttls => (
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?,
+            587,
+        ),
+        SmtpSecurity::Tls => (
+            AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+            465,
+        ),
+        SmtpSecurity::None => (
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+            25,
+        ),
+    };
+    let mut builder = builder
+        .port(config.port.unwrap_or(port))
+        .timeout(Some(Duration::from_secs(config.timeout)))
+        .pool_config(PoolConfig::new().max_size(config.pool_size));
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        builder = builder.credentials(Credentials::new(
+            username.clone(),
+            resolve_secret(password)?,
+        ));
+    }
+    Ok(builder.build())
+}
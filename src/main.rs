@@ -1,43 +1,40 @@
-use diesel_async::pooled_connection::deadpool::Pool;
-use diesel_async::AsyncMysqlConnection;
-use once_cell::sync::Lazy;
+use clap::Parser;
 use rustls_pemfile::certs;
-use serde::Deserialize;
-use std::path::Path;
-use std::{
-    fs::{self, File},
-    io::BufReader,
-    sync::Arc,
-};
+use std::{fs::File, io::BufReader, sync::Arc};
+use tokio::sync::watch;
 use tokio_rustls::rustls::ServerConfig;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+mod auth;
+mod cli;
+mod config;
 mod diesel;
 mod errors;
 mod http;
+mod jwt;
 //#[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
 //mod mail;
+mod mailer;
 pub mod models;
+mod page;
+mod plugin;
 mod query_helper;
+mod repo;
 mod rpc;
 pub mod schema;
+mod signature;
+mod speedupdate;
+mod storage;
 mod surrealdb;
 mod user;
 mod utils;
-
-#[derive(Debug, Deserialize)]
-struct LucleConfig {
-    database: DatabaseConfig,
-}
-
-#[derive(Debug, Deserialize)]
-struct DatabaseConfig {
-    database: String,
-}
+mod wasm;
 
 pub enum DbType {
-    Mysql(Lazy<Pool<AsyncMysqlConnection>>),
-    Surrealdb(i32),
+    Mysql(String),
+    Postgresql(String),
+    Sqlite(String),
+    Surrealdb(String),
     NoDatabase,
 }
 
@@ -58,48 +55,62 @@ async fn main() {
         )
         .init();
 
-    let config_file = "config.toml";
-    let mut db = DbType::NoDatabase;
-    match fs::read_to_string(config_file) {
-        Ok(content) => {
-            let decoded: LucleConfig = toml::from_str(&content).unwrap();
-            match decoded.database.database.as_str() {
-                "mysql" => db = DbType::Mysql(diesel::create_pool()),
-                "surrealdb" => db = DbType::Surrealdb(12),
-                &_ => db = DbType::NoDatabase,
+    let cli = cli::Cli::parse();
+    let command = cli.command.unwrap_or(cli::Command::Serve);
+    if let cli::Command::Config {
+        action: cli::ConfigCommand::Check,
+    } = command
+    {
+        std::process::exit(cli::check_config(&cli.config));
+    }
+
+    let config = if command.needs_jwt_keys() {
+        config::LucleConfig::load(&cli.config)
+    } else {
+        config::LucleConfig::load_without_jwt_keys(&cli.config)
+    };
+    let config = match config {
+        Ok(config) => Arc::new(config),
+        Err(errors) => {
+            for err in errors {
+                tracing::error!("{}", err);
             }
+            std::process::exit(1);
         }
-        Err(err) => tracing::error!("Unable to get config: {}", err),
     };
 
-    if !Path::new(".tls/ca_cert.pem").exists()
-        || !Path::new(".tls/server_cert.pem").exists()
-        || !Path::new(".tls/server_private_key.pem").exists()
-    {
-        let pki = Arc::new(utils::Pki::new());
-
-        if let Err(err) = std::fs::create_dir_all(".tls") {
-            tracing::error!("{}", err);
-        }
-        if let Err(err) = utils::write_pem(".tls/ca_cert.pem", &pki.ca_cert.cert.pem()) {
-            tracing::error!("{}", err);
-        }
-        if let Err(err) = utils::write_pem(".tls/server_cert.pem", &pki.server_cert.cert.pem()) {
-            tracing::error!("{}", err);
+    match command {
+        cli::Command::Serve => serve(config).await,
+        command => {
+            if let Err(err) = cli::run(command, &config).await {
+                tracing::error!("{}", err);
+                std::process::exit(1);
+            }
         }
-        if let Err(err) = utils::write_pem(
-            ".tls/server_private_key.pem",
-            &pki.server_cert.key_pair.serialize_pem(),
-        ) {
-            tracing::error!("{}", err);
+    }
+}
+
+async fn serve(config: Arc<config::LucleConfig>) {
+    let db = match config.database.db_type() {
+        Ok(db_type) => db_type,
+        Err(err) => {
+            tracing::error!("Unable to get database config: {}", err);
+            DbType::NoDatabase
         }
+    };
+
+    let tls_dir = &config.tls.directory;
+    let server_cert_path = tls_dir.join("server_cert.pem");
+    let server_key_path = tls_dir.join("server_private_key.pem");
+    if let Err(err) = utils::init_pki(tls_dir, false) {
+        tracing::error!("{}", err);
     }
 
-    let cert_file = File::open(".tls/server_cert.pem").unwrap();
+    let cert_file = File::open(&server_cert_path).unwrap();
     let mut cert_buf = BufReader::new(cert_file);
     let certs = certs(&mut cert_buf).map(|result| result.unwrap()).collect();
 
-    let key_file = File::open(".tls/server_private_key.pem").unwrap();
+    let key_file = File::open(&server_key_path).unwrap();
     let mut key_buf = BufReader::new(key_file);
     let private_key = rustls_pemfile::private_key(&mut key_buf).unwrap().unwrap();
 
@@ -108,15 +119,49 @@ async fn main() {
         .with_single_cert(certs, private_key)
         .unwrap();
 
-    //    #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
-    //    tokio::spawn(async { mail::start_mail_server().await });
+    // Every server stops on a signal, or when one of them fails
+    let (shutdown_tx, shutdown) = watch::channel(false);
+    let shutdown_tx = Arc::new(shutdown_tx);
+    let signal_tx = shutdown_tx.clone();
+    tokio::spawn(async move {
+        utils::shutdown_signal().await;
+        tracing::info!("Shutting down");
+        let _ = signal_tx.send(true);
+    });
+    let storage = match storage::connect(db).await {
+        Ok(storage) => storage::SharedStorage::new(storage),
+        Err(err) => {
+            tracing::error!("GRPC server doesn't start: {err}");
+            return;
+        }
+    };
+    let plugins = Arc::new(plugin::PluginHost::load(&config.plugins, storage.clone()).await);
+    plugins.run_hooks(plugin::HookEvent::Start).await;
 
-    if let Err(err) = tokio::join!(
-        rpc::rpc_api(&mut cert_buf, &mut key_buf, db),
-        http::serve_dir()
-    )
-    .0
-    {
-        tracing::error!("GRPC and HTTP server doesn't start: {err}");
-    }
+    let rpc = async {
+        if let Err(err) = rpc::rpc_api(
+            &mut cert_buf,
+            &mut key_buf,
+            storage.clone(),
+            config.clone(),
+            plugins.clone(),
+            shutdown.clone(),
+        )
+        .await
+        {
+            tracing::error!("GRPC server doesn't start: {err}");
+            let _ = shutdown_tx.send(true);
+        }
+    };
+    tokio::join!(
+        rpc,
+        http::serve_dir(
+            config.listeners.http,
+            &config.paths.web,
+            storage.clone(),
+            plugins.clone(),
+            shutdown.clone()
+        )
+    );
+    plugins.run_hooks(plugin::HookEvent::Stop).await;
 }
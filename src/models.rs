@@ -1,6 +1,9 @@
 use super::schema::{
-    repositories, sql_types::UsersRepositoriesPermissionEnum, users, users_repositories,
+    page_revisions, pages, plugins, refresh_tokens, repositories,
+    sql_types::{PagesStatusEnum, UsersRepositoriesPermissionEnum, UsersRoleEnum},
+    users, users_repositories,
 };
+use crate::errors::Error;
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use diesel::FromSqlRow;
@@ -9,11 +12,13 @@ use diesel::{
     serialize::{self, IsNull, Output, ToSql},
     AsExpression,
 };
+use serde::{Deserialize, Serialize};
 use std::io::Write;
+use std::str::FromStr;
 
-#[derive(Debug, Queryable, Selectable)]
+#[derive(Debug, Queryable, Selectable, Deserialize)]
 #[diesel(table_name = users)]
-#[diesel(check_for_backend(diesel::mysql::Mysql))]
+#[diesel(check_for_backend(diesel::mysql::Mysql, diesel::pg::Pg, diesel::sqlite::Sqlite))]
 pub struct User {
     pub id: i32,
     pub username: String,
@@ -22,9 +27,14 @@ pub struct User {
     pub created_at: NaiveDateTime,
     pub modified_at: NaiveDateTime,
     pub reset_token: Option<String>,
+    #[serde(default)]
+    pub role: Role,
+    /// Language of the emails sent to the user, e.g. `fr` or `fr-CA`
+    #[serde(default)]
+    pub locale: String,
 }
 
-#[derive(Insertable)]
+#[derive(Insertable, Serialize)]
 #[diesel(table_name = users)]
 pub struct NewUser {
     pub username: String,
@@ -32,33 +42,120 @@ pub struct NewUser {
     pub email: String,
     pub created_at: NaiveDateTime,
     pub modified_at: NaiveDateTime,
+    pub role: Role,
+    pub locale: String,
 }
 
-#[derive(Insertable)]
+/// Issued refresh token, `id` is the `jti` claim. Refreshing revokes the
+/// token used, so a revoked token coming back means it was stolen.
+#[derive(Debug, Insertable, Queryable, Selectable, Serialize, Deserialize)]
+#[diesel(table_name = refresh_tokens)]
+#[diesel(check_for_backend(diesel::mysql::Mysql, diesel::pg::Pg, diesel::sqlite::Sqlite))]
+pub struct RefreshToken {
+    pub id: String,
+    pub user_id: i32,
+    pub expires_at: NaiveDateTime,
+    pub revoked: bool,
+}
+
+#[derive(Debug, Insertable, Queryable, Selectable, Serialize, Deserialize)]
 #[diesel(table_name = repositories)]
-#[diesel(check_for_backend(diesel::mysql::Mysql))]
+#[diesel(check_for_backend(diesel::mysql::Mysql, diesel::pg::Pg, diesel::sqlite::Sqlite))]
 pub struct Repository {
     pub name: String,
     pub created_at: NaiveDateTime,
+    /// User who registered the repository or received it by transfer, unset
+    /// for repositories left without a member with write access
+    pub owner_id: Option<i32>,
 }
 
-#[derive(Insertable, Selectable, Queryable, Debug, PartialEq)]
+#[derive(Insertable, Selectable, Queryable, Debug, PartialEq, Serialize, Deserialize)]
 #[diesel(table_name = users_repositories)]
-#[diesel(check_for_backend(diesel::mysql::Mysql))]
+#[diesel(check_for_backend(diesel::mysql::Mysql, diesel::pg::Pg, diesel::sqlite::Sqlite))]
 pub struct UsersRepositories {
     pub user_id: i32,
     pub repository_name: String,
     pub permission: Permission,
 }
 
-#[derive(Debug, FromSqlRow, AsExpression, PartialEq, Clone)]
+/// Persisted state of a plugin of the plugin directory, plugins without a row
+/// are enabled
+#[derive(Debug, Insertable, Queryable, Selectable, Serialize, Deserialize)]
+#[diesel(table_name = plugins)]
+#[diesel(check_for_backend(diesel::mysql::Mysql, diesel::pg::Pg, diesel::sqlite::Sqlite))]
+pub struct Plugin {
+    pub name: String,
+    pub enabled: bool,
+    pub installed_at: NaiveDateTime,
+}
+
+/// Page of the website, served by the HTTP server at its slug once published
+#[derive(Debug, Clone, Insertable, Queryable, Selectable, AsChangeset, Serialize, Deserialize)]
+#[diesel(table_name = pages)]
+#[diesel(treat_none_as_null = true)]
+#[diesel(check_for_backend(diesel::mysql::Mysql, diesel::pg::Pg, diesel::sqlite::Sqlite))]
+pub struct Page {
+    pub slug: String,
+    pub title: String,
+    /// HTML, inserted as is in the page
+    pub body: String,
+    pub author_id: i32,
+    pub status: PageStatus,
+    pub created_at: NaiveDateTime,
+    pub modified_at: NaiveDateTime,
+    /// Last time the page went from draft to published, unset for drafts
+    pub published_at: Option<NaiveDateTime>,
+    /// Number of the revision holding the current title and body
+    pub revision: i32,
+}
+
+/// Title and body of a page saved by an editor, numbered from 1 for each page
+#[derive(Debug, Clone, Insertable, Queryable, Selectable, Serialize, Deserialize)]
+#[diesel(table_name = page_revisions)]
+#[diesel(check_for_backend(diesel::mysql::Mysql, diesel::pg::Pg, diesel::sqlite::Sqlite))]
+pub struct PageRevision {
+    pub page_slug: String,
+    pub revision: i32,
+    pub title: String,
+    pub body: String,
+    pub author_id: i32,
+    pub created_at: NaiveDateTime,
+}
+
+/// Access of a user to a repository, `Pending` until a member with write
+/// access approves the request to join it.
+#[derive(Debug, FromSqlRow, AsExpression, PartialEq, Clone, Copy, Serialize, Deserialize)]
 #[diesel(sql_type = UsersRepositoriesPermissionEnum)]
+#[serde(rename_all = "lowercase")]
 pub enum Permission {
     Write,
     Read,
     Pending,
 }
 
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Permission::Read => "read",
+            Permission::Write => "write",
+            Permission::Pending => "pending",
+        }
+    }
+}
+
+impl FromStr for Permission {
+    type Err = Error;
+
+    fn from_str(permission: &str) -> Result<Self, Self::Err> {
+        match permission {
+            "read" => Ok(Permission::Read),
+            "write" => Ok(Permission::Write),
+            "pending" => Ok(Permission::Pending),
+            _ => Err(Error::InvalidPermission(permission.to_string())),
+        }
+    }
+}
+
 impl ToSql<UsersRepositoriesPermissionEnum, diesel::mysql::Mysql> for Permission {
     fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, diesel::mysql::Mysql>) -> serialize::Result {
         match *self {
@@ -80,3 +177,195 @@ impl FromSql<UsersRepositoriesPermissionEnum, diesel::mysql::Mysql> for Permissi
         }
     }
 }
+
+impl ToSql<UsersRepositoriesPermissionEnum, diesel::pg::Pg> for Permission {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, diesel::pg::Pg>) -> serialize::Result {
+        match *self {
+            Permission::Read => out.write_all(b"read")?,
+            Permission::Write => out.write_all(b"write")?,
+            Permission::Pending => out.write_all(b"pending")?,
+        }
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<UsersRepositoriesPermissionEnum, diesel::pg::Pg> for Permission {
+    fn from_sql(bytes: diesel::pg::PgValue<'_>) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"read" => Ok(Permission::Read),
+            b"write" => Ok(Permission::Write),
+            b"pending" => Ok(Permission::Pending),
+            _ => Err("Unrecognized enum variant".into()),
+        }
+    }
+}
+
+impl ToSql<UsersRepositoriesPermissionEnum, diesel::sqlite::Sqlite> for Permission {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, diesel::sqlite::Sqlite>) -> serialize::Result {
+        match *self {
+            Permission::Read => out.set_value("read"),
+            Permission::Write => out.set_value("write"),
+            Permission::Pending => out.set_value("pending"),
+        }
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<UsersRepositoriesPermissionEnum, diesel::sqlite::Sqlite> for Permission {
+    fn from_sql(mut bytes: diesel::sqlite::SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        match bytes.read_text() {
+            "read" => Ok(Permission::Read),
+            "write" => Ok(Permission::Write),
+            "pending" => Ok(Permission::Pending),
+            _ => Err("Unrecognized enum variant".into()),
+        }
+    }
+}
+
+/// Global role of a user: admins manage users and databases, maintainers
+/// manage repositories and members use the repositories they joined.
+#[derive(
+    Debug, Default, FromSqlRow, AsExpression, PartialEq, Clone, Copy, Serialize, Deserialize,
+)]
+#[diesel(sql_type = UsersRoleEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    Maintainer,
+    #[default]
+    Member,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Role::Admin => "admin",
+            Role::Maintainer => "maintainer",
+            Role::Member => "member",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = Error;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "admin" => Ok(Role::Admin),
+            "maintainer" => Ok(Role::Maintainer),
+            "" | "member" => Ok(Role::Member),
+            _ => Err(Error::InvalidRole(role.to_string())),
+        }
+    }
+}
+
+impl ToSql<UsersRoleEnum, diesel::mysql::Mysql> for Role {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, diesel::mysql::Mysql>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<UsersRoleEnum, diesel::mysql::Mysql> for Role {
+    fn from_sql(bytes: diesel::mysql::MysqlValue) -> deserialize::Result<Self> {
+        Ok(std::str::from_utf8(bytes.as_bytes())?.parse()?)
+    }
+}
+
+impl ToSql<UsersRoleEnum, diesel::pg::Pg> for Role {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, diesel::pg::Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<UsersRoleEnum, diesel::pg::Pg> for Role {
+    fn from_sql(bytes: diesel::pg::PgValue<'_>) -> deserialize::Result<Self> {
+        Ok(std::str::from_utf8(bytes.as_bytes())?.parse()?)
+    }
+}
+
+impl ToSql<UsersRoleEnum, diesel::sqlite::Sqlite> for Role {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, diesel::sqlite::Sqlite>) -> serialize::Result {
+        out.set_value(self.as_str());
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<UsersRoleEnum, diesel::sqlite::Sqlite> for Role {
+    fn from_sql(mut bytes: diesel::sqlite::SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        Ok(bytes.read_text().parse()?)
+    }
+}
+
+/// Drafts are only seen by the editors of the pages
+#[derive(
+    Debug, Default, FromSqlRow, AsExpression, PartialEq, Clone, Copy, Serialize, Deserialize,
+)]
+#[diesel(sql_type = PagesStatusEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PageStatus {
+    #[default]
+    Draft,
+    Published,
+}
+
+impl PageStatus {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            PageStatus::Draft => "draft",
+            PageStatus::Published => "published",
+        }
+    }
+}
+
+impl FromStr for PageStatus {
+    type Err = Error;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            "" | "draft" => Ok(PageStatus::Draft),
+            "published" => Ok(PageStatus::Published),
+            _ => Err(Error::InvalidPageStatus(status.to_string())),
+        }
+    }
+}
+
+impl ToSql<PagesStatusEnum, diesel::mysql::Mysql> for PageStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, diesel::mysql::Mysql>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<PagesStatusEnum, diesel::mysql::Mysql> for PageStatus {
+    fn from_sql(bytes: diesel::mysql::MysqlValue) -> deserialize::Result<Self> {
+        Ok(std::str::from_utf8(bytes.as_bytes())?.parse()?)
+    }
+}
+
+impl ToSql<PagesStatusEnum, diesel::pg::Pg> for PageStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, diesel::pg::Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<PagesStatusEnum, diesel::pg::Pg> for PageStatus {
+    fn from_sql(bytes: diesel::pg::PgValue<'_>) -> deserialize::Result<Self> {
+        Ok(std::str::from_utf8(bytes.as_bytes())?.parse()?)
+    }
+}
+
+impl ToSql<PagesStatusEnum, diesel::sqlite::Sqlite> for PageStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, diesel::sqlite::Sqlite>) -> serialize::Result {
+        out.set_value(self.as_str());
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<PagesStatusEnum, diesel::sqlite::Sqlite> for PageStatus {
+    fn from_sql(mut bytes: diesel::sqlite::SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        Ok(bytes.read_text().parse()?)
+    }
+}
@@ -0,0 +1,616 @@
+use crate::errors::Error;
+use crate::models::{Page, PageRevision, PageStatus, Role, User};
+use crate::storage::Storage;
+use std::collections::HashMap;
+use tera::{Context, Tera};
+
+const PAGE_TEMPLATE: &str = include_str!("templates/page.html");
+
+/// Sent with every page, a sandboxed document runs no script and has no
+/// access to the storage of the web app
+pub const CONTENT_SECURITY_POLICY: &str = "default-src 'none'; img-src 'self' https: data:; \
+    media-src 'self' https:; base-uri 'none'; form-action 'none'; frame-ancestors 'none'; sandbox";
+
+/// Roles allowed to see drafts and edit pages
+const EDITORS: &[Role] = &[Role::Admin, Role::Maintainer];
+
+/// Times a save is merged again with the page saved meanwhile by other editors
+/// before giving up
+const SAVE_ATTEMPTS: usize = 3;
+
+/// What an editor writes, the rest of the page is kept by lucle
+pub struct PageContent {
+    pub slug: String,
+    pub title: String,
+    pub body: String,
+    /// `draft` or `published`, empty for a draft
+    pub status: String,
+}
+
+pub struct PageInfo {
+    pub page: Page,
+    /// Username of the author, unset when the user no longer exists
+    pub author: Option<String>,
+}
+
+pub struct RevisionInfo {
+    pub revision: PageRevision,
+    /// Username of the editor who saved it, unset when the user no longer
+    /// exists
+    pub author: Option<String>,
+}
+
+pub enum PageSave {
+    Saved(PageInfo),
+    /// The edits overlap changes saved by another editor and nothing is
+    /// saved. `conflicts` is the merged body with conflict markers, to save
+    /// again from the revision of `current` once resolved.
+    Conflict {
+        current: PageInfo,
+        conflicts: String,
+    },
+}
+
+/// Slugs are the path of the page without its leading `/`, like `about` or
+/// `docs/install`. Static files of the web app and plugin routes take
+/// precedence over pages.
+pub fn is_valid_slug(slug: &str) -> bool {
+    !slug.is_empty()
+        && slug.len() <= 255
+        && slug.split('/').all(|segment| {
+            !segment.is_empty()
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+        })
+}
+
+/// Every page, drafts included, ordered by slug
+pub async fn list_pages(storage: &dyn Storage, caller: &str) -> Result<Vec<PageInfo>, Error> {
+    require_editor(storage, caller).await?;
+    let authors = usernames(storage).await?;
+    Ok(storage
+        .list_pages()
+        .await?
+        .into_iter()
+        .map(|page| PageInfo {
+            author: authors.get(&page.author_id).cloned(),
+            page,
+        })
+        .collect())
+}
+
+pub async fn get_page(storage: &dyn Storage, caller: &str, slug: &str) -> Result<PageInfo, Error> {
+    require_editor(storage, caller).await?;
+    let page = find_page(storage, slug).await?;
+    page_info(storage, page).await
+}
+
+pub async fn create_page(
+    storage: &dyn Storage,
+    caller: &str,
+    content: PageContent,
+) -> Result<PageInfo, Error> {
+    let author = require_editor(storage, caller).await?;
+    let status: PageStatus = content.status.parse()?;
+    check_slug(storage, &content.slug).await?;
+    let now = chrono::Utc::now().naive_utc();
+    let page = Page {
+        slug: content.slug,
+        title: content.title,
+        body: content.body,
+        author_id: author.id,
+        status,
+        created_at: now,
+        modified_at: now,
+        published_at: (status == PageStatus::Published).then_some(now),
+        revision: 1,
+    };
+    let revision = PageRevision {
+        page_slug: page.slug.clone(),
+        revision: page.revision,
+        title: page.title.clone(),
+        body: page.body.clone(),
+        author_id: author.id,
+        created_at: now,
+    };
+    storage.create_page(page.clone(), revision).await?;
+    Ok(PageInfo {
+        page,
+        author: Some(author.username),
+    })
+}
+
+/// Replace the content of a page, moving it when the slug changes. A page
+/// keeps its publication date until it goes back to draft.
+///
+/// The edits are made on `base_revision`: when other editors saved since then,
+/// their changes to the body are merged three-way with the edits, and the
+/// title is the one of the edits if it changed. A `base_revision` of 0
+/// overwrites their changes. A new revision is added when the title or the
+/// body changes.
+pub async fn update_page(
+    storage: &dyn Storage,
+    caller: &str,
+    slug: &str,
+    content: PageContent,
+    base_revision: i32,
+) -> Result<PageSave, Error> {
+    let editor = require_editor(storage, caller).await?;
+    let status: PageStatus = content.status.parse()?;
+    if content.slug != slug {
+        check_slug(storage, &content.slug).await?;
+    }
+    let base = match base_revision {
+        0 => None,
+        base_revision => Some(find_revision(storage, slug, base_revision).await?),
+    };
+    // A save made meanwhile by another editor is merged like the ones made
+    // since `base_revision`
+    for _ in 0..SAVE_ATTEMPTS {
+        let page = find_page(storage, slug).await?;
+        let (title, body) = match &base {
+            Some(base) if base.revision != page.revision => {
+                let title = if content.title != base.title {
+                    content.title.clone()
+                } else {
+                    page.title.clone()
+                };
+                match diffy::merge(&base.body, &content.body, &page.body) {
+                    Ok(body) => (title, body),
+                    Err(conflicts) => {
+                        return Ok(PageSave::Conflict {
+                            current: page_info(storage, page).await?,
+                            conflicts,
+                        })
+                    }
+                }
+            }
+            _ => (content.title.clone(), content.body.clone()),
+        };
+
+        let now = chrono::Utc::now().naive_utc();
+        let revision = (title != page.title || body != page.body).then(|| PageRevision {
+            page_slug: content.slug.clone(),
+            revision: page.revision + 1,
+            title: title.clone(),
+            body: body.clone(),
+            author_id: editor.id,
+            created_at: now,
+        });
+        let published_at = match status {
+            PageStatus::Draft => None,
+            PageStatus::Published => page.published_at.or(Some(now)),
+        };
+        let read_revision = page.revision;
+        let page = Page {
+            slug: content.slug.clone(),
+            title,
+            body,
+            status,
+            modified_at: now,
+            published_at,
+            revision: revision
+                .as_ref()
+                .map_or(page.revision, |revision| revision.revision),
+            ..page
+        };
+        if storage
+            .update_page(slug, read_revision, page.clone(), revision)
+            .await?
+        {
+            return Ok(PageSave::Saved(page_info(storage, page).await?));
+        }
+    }
+    Err(Error::PageChanged(slug.to_string()))
+}
+
+/// Restore the title and body of `revision` as a new revision
+pub async fn rollback_page(
+    storage: &dyn Storage,
+    caller: &str,
+    slug: &str,
+    revision: i32,
+) -> Result<PageInfo, Error> {
+    let editor = require_editor(storage, caller).await?;
+    let old = find_revision(storage, slug, revision).await?;
+    for _ in 0..SAVE_ATTEMPTS {
+        let page = find_page(storage, slug).await?;
+        let now = chrono::Utc::now().naive_utc();
+        let revision = PageRevision {
+            revision: page.revision + 1,
+            author_id: editor.id,
+            created_at: now,
+            ..old.clone()
+        };
+        let read_revision = page.revision;
+        let page = Page {
+            title: revision.title.clone(),
+            body: revision.body.clone(),
+            modified_at: now,
+            revision: revision.revision,
+            ..page
+        };
+        if storage
+            .update_page(slug, read_revision, page.clone(), Some(revision))
+            .await?
+        {
+            return page_info(storage, page).await;
+        }
+    }
+    Err(Error::PageChanged(slug.to_string()))
+}
+
+/// Every revision of a page, oldest first
+pub async fn list_revisions(
+    storage: &dyn Storage,
+    caller: &str,
+    slug: &str,
+) -> Result<Vec<RevisionInfo>, Error> {
+    require_editor(storage, caller).await?;
+    find_page(storage, slug).await?;
+    let authors = usernames(storage).await?;
+    Ok(storage
+        .list_page_revisions(slug)
+        .await?
+        .into_iter()
+        .map(|revision| RevisionInfo {
+            author: authors.get(&revision.author_id).cloned(),
+            revision,
+        })
+        .collect())
+}
+
+/// Unified diff of the body from revision `from` to revision `to`
+pub async fn diff_revisions(
+    storage: &dyn Storage,
+    caller: &str,
+    slug: &str,
+    from: i32,
+    to: i32,
+) -> Result<String, Error> {
+    require_editor(storage, caller).await?;
+    let from = find_revision(storage, slug, from).await?;
+    let to = find_revision(storage, slug, to).await?;
+    Ok(diffy::create_patch(&from.body, &to.body).to_string())
+}
+
+pub async fn delete_page(storage: &dyn Storage, caller: &str, slug: &str) -> Result<(), Error> {
+    require_editor(storage, caller).await?;
+    find_page(storage, slug).await?;
+    storage.delete_page(slug).await
+}
+
+/// HTML document of the page at `slug`, if it is published. Pages are served
+/// on the origin of the web app, so the body goes through an allow-list of
+/// tags and attributes that drops scripts, event handlers and `javascript:`
+/// links.
+pub async fn render_published(storage: &dyn Storage, slug: &str) -> Result<Option<String>, Error> {
+    match storage.get_page(slug).await? {
+        Some(page) if page.status == PageStatus::Published => {
+            let mut context = Context::new();
+            context.insert("title", &page.title);
+            context.insert("body", &ammonia::clean(&page.body));
+            Ok(Some(Tera::one_off(PAGE_TEMPLATE, &context, true)?))
+        }
+        _ => Ok(None),
+    }
+}
+
+async fn require_editor(storage: &dyn Storage, caller: &str) -> Result<User, Error> {
+    match storage.get_user_by_username(caller).await? {
+        Some(user) if EDITORS.contains(&user.role) => Ok(user),
+        Some(_) => Err(Error::PermissionDenied),
+        None => Err(Error::UserNotFound),
+    }
+}
+
+async fn usernames(storage: &dyn Storage) -> Result<HashMap<i32, String>, Error> {
+    Ok(storage
+        .list_users()
+        .await?
+        .into_iter()
+        .map(|user| (user.id, user.username))
+        .collect())
+}
+
+async fn find_page(storage: &dyn Storage, slug: &str) -> Result<Page, Error> {
+    storage
+        .get_page(slug)
+        .await?
+        .ok_or_else(|| Error::PageNotFound(slug.to_string()))
+}
+
+async fn find_revision(
+    storage: &dyn Storage,
+    slug: &str,
+    revision: i32,
+) -> Result<PageRevision, Error> {
+    storage
+        .get_page_revision(slug, revision)
+        .await?
+        .ok_or_else(|| Error::PageRevisionNotFound(slug.to_string(), revision))
+}
+
+async fn page_info(storage: &dyn Storage, page: Page) -> Result<PageInfo, Error> {
+    let author = storage
+        .get_user_by_id(page.author_id)
+        .await?
+        .map(|user| user.username);
+    Ok(PageInfo { page, author })
+}
+
+async fn check_slug(storage: &dyn Storage, slug: &str) -> Result<(), Error> {
+    if !is_valid_slug(slug) {
+        return Err(Error::InvalidSlug(slug.to_string()));
+    }
+    if storage.get_page(slug).await?.is_some() {
+        return Err(Error::PageExists(slug.to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::storages;
+    use std::sync::Arc;
+
+    const BODY: &str = "first line\nsecond line\nthird line\nfourth line\n";
+
+    /// A database of each backend with an admin, `editor`, and a page at
+    /// `doc`
+    async fn storages_with_page(name: &str) -> [Arc<dyn Storage>; 2] {
+        let storages = storages(name).await;
+        for storage in &storages {
+            crate::user::create_first_user(
+                &**storage,
+                "editor".to_string(),
+                "correct horse battery".to_string(),
+                "editor@example.com".to_string(),
+                "en".to_string(),
+            )
+            .await
+            .unwrap();
+            create_page(&**storage, "editor", content("Title", BODY))
+                .await
+                .unwrap();
+        }
+        storages
+    }
+
+    fn content(title: &str, body: &str) -> PageContent {
+        PageContent {
+            slug: "doc".to_string(),
+            title: title.to_string(),
+            body: body.to_string(),
+            status: "published".to_string(),
+        }
+    }
+
+    async fn save(storage: &dyn Storage, title: &str, body: &str, base_revision: i32) -> PageSave {
+        update_page(
+            storage,
+            "editor",
+            "doc",
+            content(title, body),
+            base_revision,
+        )
+        .await
+        .unwrap()
+    }
+
+    fn saved(save: PageSave) -> Page {
+        match save {
+            PageSave::Saved(info) => info.page,
+            PageSave::Conflict { conflicts, .. } => panic!("unexpected conflict:\n{}", conflicts),
+        }
+    }
+
+    #[tokio::test]
+    async fn edits_add_revisions() {
+        for storage in storages_with_page("page-revisions").await {
+            let storage = &*storage;
+            let body = BODY.replace("first", "1st");
+            let page = saved(save(storage, "Title", &body, 1).await);
+            assert_eq!((page.revision, page.body.as_str()), (2, body.as_str()));
+
+            // Only the status changes, there is nothing new to keep
+            let page = update_page(
+                storage,
+                "editor",
+                "doc",
+                PageContent {
+                    status: "draft".to_string(),
+                    ..content("Title", &body)
+                },
+                2,
+            )
+            .await
+            .map(saved)
+            .unwrap();
+            assert_eq!((page.revision, page.status), (2, PageStatus::Draft));
+            assert_eq!(
+                list_revisions(storage, "editor", "doc")
+                    .await
+                    .unwrap()
+                    .len(),
+                2
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn moved_page_keeps_its_revisions() {
+        for storage in storages_with_page("page-move").await {
+            let storage = &*storage;
+            create_page(
+                storage,
+                "editor",
+                PageContent {
+                    slug: "taken".to_string(),
+                    ..content("Taken", BODY)
+                },
+            )
+            .await
+            .unwrap();
+            let moved = PageContent {
+                slug: "docs/moved".to_string(),
+                ..content("Title", &BODY.replace("first", "1st"))
+            };
+            let page = saved(
+                update_page(storage, "editor", "doc", moved, 1)
+                    .await
+                    .unwrap(),
+            );
+            assert_eq!((page.slug.as_str(), page.revision), ("docs/moved", 2));
+
+            assert!(storage.get_page("doc").await.unwrap().is_none());
+            assert!(matches!(
+                get_page(storage, "editor", "doc").await,
+                Err(Error::PageNotFound(_))
+            ));
+            let revisions = list_revisions(storage, "editor", "docs/moved")
+                .await
+                .unwrap();
+            assert_eq!(revisions.len(), 2);
+            assert!(revisions
+                .iter()
+                .all(|info| info.revision.page_slug == "docs/moved"));
+
+            let onto_taken = PageContent {
+                slug: "taken".to_string(),
+                ..content("Title", BODY)
+            };
+            assert!(matches!(
+                update_page(storage, "editor", "docs/moved", onto_taken, 0).await,
+                Err(Error::PageExists(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_edits_are_merged() {
+        for storage in storages_with_page("page-merge").await {
+            let storage = &*storage;
+            saved(save(storage, "Title", &BODY.replace("first", "1st"), 1).await);
+
+            // Made on revision 1, without the edit of revision 2
+            let page = saved(save(storage, "Title", &BODY.replace("fourth", "4th"), 1).await);
+            assert_eq!(page.revision, 3);
+            assert_eq!(page.body, "1st line\nsecond line\nthird line\n4th line\n");
+            // The title changed by neither edit stays, a changed one wins
+            assert_eq!(page.title, "Title");
+            let page = saved(save(storage, "New title", BODY, 1).await);
+            assert_eq!(page.title, "New title");
+            assert_eq!(page.body, "1st line\nsecond line\nthird line\n4th line\n");
+        }
+    }
+
+    #[tokio::test]
+    async fn overlapping_edits_conflict() {
+        for storage in storages_with_page("page-conflict").await {
+            let storage = &*storage;
+            saved(save(storage, "Title", &BODY.replace("second", "2nd"), 1).await);
+
+            match save(storage, "Title", &BODY.replace("second", "two"), 1).await {
+                PageSave::Conflict { current, conflicts } => {
+                    assert_eq!(current.page.revision, 2);
+                    assert!(conflicts.contains("<<<<<<<"), "{}", conflicts);
+                    assert!(conflicts.contains("2nd line") && conflicts.contains("two line"));
+                }
+                PageSave::Saved(_) => panic!("overlapping edits were saved"),
+            }
+            let page = storage.get_page("doc").await.unwrap().unwrap();
+            assert_eq!(
+                (page.revision, page.body),
+                (2, BODY.replace("second", "2nd"))
+            );
+
+            // Revision 0 overwrites the other edits
+            let page = saved(save(storage, "Title", &BODY.replace("second", "two"), 0).await);
+            assert_eq!(
+                (page.revision, page.body),
+                (3, BODY.replace("second", "two"))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn stale_revision_is_not_saved() {
+        for storage in storages_with_page("page-stale").await {
+            let page = saved(save(&*storage, "Title", &BODY.replace("first", "1st"), 1).await);
+
+            let stale = Page {
+                body: "lost".to_string(),
+                ..page.clone()
+            };
+            assert!(!storage.update_page("doc", 1, stale, None).await.unwrap());
+            assert_eq!(
+                storage.get_page("doc").await.unwrap().unwrap().body,
+                page.body
+            );
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn simultaneous_saves_lose_no_edit() {
+        for storage in storages_with_page("page-simultaneous").await {
+            // Edits of adjacent lines overlap, these ones are far enough apart
+            let body: String = (0..12).map(|line| format!("line {}\n", line)).collect();
+            saved(save(&*storage, "Title", &body, 1).await);
+            let saves = [0, 3, 6, 9].map(|line| {
+                let storage = storage.clone();
+                let body = body.replace(&format!("line {}\n", line), &format!("edit {}\n", line));
+                tokio::spawn(async move {
+                    update_page(&*storage, "editor", "doc", content("Title", &body), 2).await
+                })
+            });
+            let mut saved_edits = 0;
+            for save in saves {
+                match save.await.unwrap() {
+                    Ok(save) => {
+                        saved(save);
+                        saved_edits += 1;
+                    }
+                    // Gave up after too many saves of the other editors
+                    Err(Error::PageChanged(_)) => {}
+                    Err(err) => panic!("{}", err),
+                }
+            }
+            let page = storage.get_page("doc").await.unwrap().unwrap();
+            assert_eq!(page.body.matches("edit").count(), saved_edits);
+            assert_eq!(page.revision as usize, 2 + saved_edits);
+        }
+    }
+
+    #[tokio::test]
+    async fn rollback_restores_a_revision() {
+        for storage in storages_with_page("page-rollback").await {
+            let storage = &*storage;
+            saved(save(storage, "Renamed", &BODY.replace("first", "1st"), 1).await);
+
+            let page = rollback_page(storage, "editor", "doc", 1)
+                .await
+                .unwrap()
+                .page;
+            assert_eq!((page.revision, page.title.as_str()), (3, "Title"));
+            assert_eq!(page.body, BODY);
+            let revisions = list_revisions(storage, "editor", "doc").await.unwrap();
+            let titles: Vec<_> = revisions
+                .iter()
+                .map(|info| (info.revision.revision, info.revision.title.as_str()))
+                .collect();
+            assert_eq!(titles, [(1, "Title"), (2, "Renamed"), (3, "Title")]);
+
+            let patch = diff_revisions(storage, "editor", "doc", 2, 3)
+                .await
+                .unwrap();
+            assert!(patch.contains("-1st line\n+first line\n"), "{}", patch);
+            assert!(matches!(
+                rollback_page(storage, "editor", "doc", 9).await,
+                Err(Error::PageRevisionNotFound(_, 9))
+            ));
+        }
+    }
+}
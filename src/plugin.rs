@@ -0,0 +1,1478 @@
+use crate::auth::Identity;
+use crate::config::PluginsConfig;
+use crate::errors::Error;
+use crate::signature::{self, TrustedKey};
+use crate::storage::{SharedStorage, Storage};
+use crate::wasm::{WasmHost, WasmLimits, WasmModule};
+use axum::{
+    body::Body,
+    extract::{Path as RoutePath, Request as HttpRequest},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response as HttpResponse},
+};
+use dlopen2::raw::Library;
+use flate2::read::GzDecoder;
+use prost::bytes::{Buf, BufMut};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::{c_char, c_void, CStr, CString},
+    path::{Path, PathBuf},
+    ptr,
+    sync::{Arc, RwLock},
+};
+use tar::EntryType;
+use tokio::sync::{broadcast, Mutex};
+use tonic::{
+    codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder},
+    Code, Status,
+};
+use tower::service_fn;
+
+/// Version of the C ABI below and of the WebAssembly interface, plugins built
+/// for another one are rejected
+pub const ABI_VERSION: u32 = 1;
+const MANIFEST_FILE: &str = "plugin.toml";
+/// Largest HTTP request body handed to a plugin
+const MAX_BODY_SIZE: usize = 4 * 1024 * 1024;
+/// gRPC services of lucle itself, plugins cannot add methods to them
+const RESERVED_SERVICES: &[&str] = &["/luclerpc.", "/speedupdate."];
+
+/// `plugin.toml`, next to the library in the directory of the plugin
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub description: String,
+    pub abi_version: u32,
+    #[serde(default)]
+    pub runtime: Runtime,
+    /// File name of the shared library or WebAssembly module in the plugin
+    /// directory, signed by one of `[plugins] trusted_keys` in
+    /// `<library>.minisig`
+    pub library: PathBuf,
+    /// Read by WebAssembly plugins through the host API
+    #[serde(default)]
+    pub config: HashMap<String, String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Runtime {
+    /// Shared library loaded in the server process
+    #[default]
+    Native,
+    /// WebAssembly module run in a sandbox, see `wasm`
+    Wasm,
+}
+
+// C ABI. A plugin exports:
+//
+// - `uint32_t lucle_plugin_abi_version(void)`, returning `ABI_VERSION`
+// - `int32_t lucle_plugin_register_v1(const Registrar *)`, called once after
+//   loading to register routes and hooks, returning 0 on success
+// - `void lucle_plugin_free_response(PluginResponse *)`, releasing what a
+//   handler allocated in the response
+//
+// Handlers and hooks are called from several threads at once.
+
+/// Request handed to a handler, valid for the duration of the call
+#[repr(C)]
+pub struct PluginRequest {
+    /// HTTP method, `POST` for gRPC methods
+    pub method: *const c_char,
+    /// Path of the HTTP route below `/plugins/<name>`, or full gRPC method
+    pub path: *const c_char,
+    /// Authenticated caller of a gRPC method, null for HTTP routes
+    pub username: *const c_char,
+    /// HTTP body, or the encoded protobuf message
+    pub body: *const u8,
+    pub body_len: usize,
+}
+
+/// Filled by a handler and released by `lucle_plugin_free_response`
+#[repr(C)]
+pub struct PluginResponse {
+    /// HTTP status, or gRPC code where a non zero code makes `body` the
+    /// error message
+    pub status: u32,
+    /// Optional HTTP content type
+    pub content_type: *mut c_char,
+    pub body: *mut u8,
+    pub body_len: usize,
+}
+
+pub type Handler = unsafe extern "C" fn(
+    user_data: *mut c_void,
+    request: *const PluginRequest,
+    response: *mut PluginResponse,
+);
+pub type Hook = unsafe extern "C" fn(user_data: *mut c_void, event: u32);
+
+/// Handed to `lucle_plugin_register_v1`, each function takes `context` as
+/// first argument and returns 0 on success
+#[repr(C)]
+pub struct Registrar {
+    pub context: *mut c_void,
+    /// Add a unary gRPC method, `path` is `/<package>.<Service>/<Method>`
+    pub grpc_method: unsafe extern "C" fn(
+        context: *mut c_void,
+        path: *const c_char,
+        handler: Handler,
+        user_data: *mut c_void,
+    ) -> i32,
+    /// Add an HTTP route served at `/plugins/<name><path>`
+    pub http_route: unsafe extern "C" fn(
+        context: *mut c_void,
+        method: *const c_char,
+        path: *const c_char,
+        handler: Handler,
+        user_data: *mut c_void,
+    ) -> i32,
+    /// Call `hook` on a `HookEvent`
+    pub hook: unsafe extern "C" fn(
+        context: *mut c_void,
+        event: u32,
+        hook: Hook,
+        user_data: *mut c_void,
+    ) -> i32,
+}
+
+type AbiVersionFn = unsafe extern "C" fn() -> u32;
+type RegisterFn = unsafe extern "C" fn(registrar: *const Registrar) -> i32;
+type FreeResponseFn = unsafe extern "C" fn(response: *mut PluginResponse);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u32)]
+pub enum HookEvent {
+    /// The servers are starting
+    Start = 0,
+    /// The servers stopped
+    Stop = 1,
+}
+
+/// Pointer owned by the plugin, handed back to its callbacks
+#[derive(Clone, Copy)]
+struct UserData(*mut c_void);
+
+// SAFETY: plugins are required to accept calls from any thread
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+impl UserData {
+    fn as_ptr(self) -> *mut c_void {
+        self.0
+    }
+}
+
+struct LoadedLibrary {
+    /// Unloaded once every route and hook of the plugin is dropped
+    _library: Library,
+    free_response: FreeResponseFn,
+}
+
+/// gRPC method or HTTP route of a plugin
+#[derive(Clone)]
+pub struct Route {
+    plugin: String,
+    endpoint: Endpoint,
+}
+
+#[derive(Clone)]
+enum Endpoint {
+    Native {
+        handler: Handler,
+        user_data: UserData,
+        library: Arc<LoadedLibrary>,
+    },
+    Wasm {
+        module: Arc<WasmModule>,
+        route: u32,
+    },
+}
+
+#[derive(Clone)]
+struct HookEntry {
+    plugin: String,
+    event: u32,
+    target: HookTarget,
+}
+
+#[derive(Clone)]
+enum HookTarget {
+    Native {
+        hook: Hook,
+        user_data: UserData,
+        _library: Arc<LoadedLibrary>,
+    },
+    Wasm(Arc<WasmModule>),
+}
+
+/// Request handed to a route
+pub struct PluginCall {
+    pub method: String,
+    pub path: String,
+    pub username: Option<String>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Default)]
+pub struct PluginReply {
+    pub status: u32,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PluginInfo {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    /// Disabled plugins stay in the plugin directory without being loaded
+    pub enabled: bool,
+    /// Why the plugin is not loaded
+    pub error: Option<String>,
+    pub grpc_methods: Vec<String>,
+    pub http_routes: Vec<String>,
+    pub directory: PathBuf,
+}
+
+impl PluginInfo {
+    /// Named after its directory until its manifest is read
+    fn new(directory: &Path) -> Self {
+        Self {
+            name: directory
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            version: String::new(),
+            description: String::new(),
+            enabled: true,
+            error: None,
+            grpc_methods: Vec::new(),
+            http_routes: Vec::new(),
+            directory: directory.to_path_buf(),
+        }
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.enabled && self.error.is_none()
+    }
+}
+
+/// Change of the plugin set, or event of a WebAssembly plugin, sent to the
+/// clients of `ServerStreamingEcho`
+#[derive(Debug, Clone, PartialEq)]
+pub enum PluginEvent {
+    Installed,
+    Enabled,
+    Disabled,
+    Unloaded,
+    Emitted { event: String, data: String },
+}
+
+/// Routes and hooks collected while a native plugin registers
+#[derive(Default)]
+struct Registration {
+    grpc: Vec<(String, Handler, UserData)>,
+    http: Vec<(String, String, Handler, UserData)>,
+    hooks: Vec<(u32, Hook, UserData)>,
+    errors: Vec<String>,
+}
+
+/// Routes and hooks of a plugin, added together once all are valid
+#[derive(Default)]
+struct Endpoints {
+    grpc: Vec<(String, Endpoint)>,
+    http: Vec<(String, String, Endpoint)>,
+    hooks: Vec<(u32, HookTarget)>,
+}
+
+/// Plugins of the plugin directory and the routes they registered
+pub struct PluginHost {
+    plugins: RwLock<Vec<PluginInfo>>,
+    /// By gRPC method path
+    grpc: RwLock<HashMap<String, Route>>,
+    /// By plugin, HTTP method and path
+    http: RwLock<HashMap<(String, String, String), Route>>,
+    hooks: RwLock<Vec<HookEntry>>,
+    /// Held while a plugin is installed, enabled, disabled or unloaded
+    lifecycle: Mutex<()>,
+    events: broadcast::Sender<(String, PluginEvent)>,
+    /// Queried by WebAssembly plugins
+    storage: SharedStorage,
+    wasm_limits: WasmLimits,
+}
+
+impl Default for PluginHost {
+    fn default() -> Self {
+        Self {
+            plugins: RwLock::default(),
+            grpc: RwLock::default(),
+            http: RwLock::default(),
+            hooks: RwLock::default(),
+            lifecycle: Mutex::new(()),
+            events: broadcast::channel(64).0,
+            storage: SharedStorage::new(None),
+            wasm_limits: PluginsConfig::default().wasm_limits(),
+        }
+    }
+}
+
+impl PluginHost {
+    /// Load every plugin found in the subdirectories of the plugin
+    /// directory, except those disabled in `storage`. Plugins that fail to
+    /// load are listed with their error.
+    pub async fn load(config: &PluginsConfig, storage: SharedStorage) -> Self {
+        let host = Self {
+            storage: storage.clone(),
+            wasm_limits: config.wasm_limits(),
+            ..Self::default()
+        };
+        let trusted_keys = match config.trusted_keys() {
+            Ok(keys) => keys,
+            Err(err) => {
+                tracing::error!("{}", err);
+                return host;
+            }
+        };
+        let disabled: HashSet<String> = match storage.current() {
+            Some(storage) => match storage.list_plugins().await {
+                Ok(plugins) => plugins
+                    .into_iter()
+                    .filter(|plugin| !plugin.enabled)
+                    .map(|plugin| plugin.name)
+                    .collect(),
+                Err(err) => {
+                    tracing::error!("Plugin states not loaded: {}", err);
+                    HashSet::new()
+                }
+            },
+            None => HashSet::new(),
+        };
+        let mut dirs = match std::fs::read_dir(&config.directory) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+                .map(|entry| entry.path())
+                .filter(|path| path.join(MANIFEST_FILE).is_file())
+                .collect::<Vec<_>>(),
+            Err(err) => {
+                tracing::info!(
+                    "No plugins loaded from {}: {}",
+                    config.directory.display(),
+                    err
+                );
+                return host;
+            }
+        };
+        dirs.sort();
+        for dir in dirs {
+            let (info, result) = host.open(&dir, &trusted_keys, &disabled).await;
+            host.plugins.write().unwrap().push(loaded(info, result));
+        }
+        host
+    }
+
+    pub fn list(&self) -> Vec<PluginInfo> {
+        self.plugins.read().unwrap().clone()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<(String, PluginEvent)> {
+        self.events.subscribe()
+    }
+
+    fn find(&self, name: &str) -> Result<PluginInfo, Error> {
+        self.plugins
+            .read()
+            .unwrap()
+            .iter()
+            .find(|plugin| plugin.name == name)
+            .cloned()
+            .ok_or_else(|| Error::PluginNotFound(name.to_string()))
+    }
+
+    fn notify(&self, name: &str, event: PluginEvent) {
+        tracing::info!("Plugin {} {:?}", name, event);
+        // No subscriber is not an error
+        let _ = self.events.send((name.to_string(), event));
+    }
+
+    /// Read the manifest of the plugin in `dir`, then unless it is
+    /// `disabled` check the signature of its library and load it. The
+    /// returned info is not added to the plugin list.
+    async fn open(
+        &self,
+        dir: &Path,
+        trusted_keys: &[TrustedKey],
+        disabled: &HashSet<String>,
+    ) -> (PluginInfo, Result<(), Error>) {
+        let mut info = PluginInfo::new(dir);
+        let result = async {
+            let manifest = read_manifest(dir)?;
+            info.name.clone_from(&manifest.name);
+            info.version.clone_from(&manifest.version);
+            info.description.clone_from(&manifest.description);
+            if self.find(&manifest.name).is_ok() {
+                return Err(Error::PluginExists(manifest.name));
+            }
+            if disabled.contains(&manifest.name) {
+                info.enabled = false;
+                return Ok(());
+            }
+            let library = dir.join(&manifest.library);
+            signature::verify_file(&library, trusted_keys, |_| {}).await?;
+            let endpoints = match manifest.runtime {
+                Runtime::Native => self.open_library(&library)?,
+                Runtime::Wasm => self.open_module(&manifest, &library).await?,
+            };
+            self.add_endpoints(&manifest.name, endpoints, &mut info)
+        }
+        .await;
+        (info, result)
+    }
+
+    /// Install a plugin from a gzipped tar archive holding `plugin.toml`,
+    /// its library and the signature of the library at its root. The plugin
+    /// is loaded, enabled and its start hooks are run.
+    pub async fn install(
+        &self,
+        config: &PluginsConfig,
+        storage: &dyn Storage,
+        archive: Vec<u8>,
+    ) -> Result<PluginInfo, Error> {
+        let _lifecycle = self.lifecycle.lock().await;
+        let trusted_keys = config.trusted_keys()?;
+        let staging = config.directory.join(".staging");
+        let _ = std::fs::remove_dir_all(&staging);
+        std::fs::create_dir_all(&staging)?;
+        let unpacked = {
+            let staging = staging.clone();
+            let max_size = config.max_size;
+            tokio::task::spawn_blocking(move || unpack(&archive, &staging, max_size))
+                .await
+                .map_err(|err| Error::Plugin(err.to_string()))?
+        };
+        let manifest = unpacked.and_then(|()| read_manifest(&staging));
+        let manifest = match manifest {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                let _ = std::fs::remove_dir_all(&staging);
+                return Err(err);
+            }
+        };
+        let dir = config.directory.join(&manifest.name);
+        if self.find(&manifest.name).is_ok() || dir.exists() {
+            let _ = std::fs::remove_dir_all(&staging);
+            return Err(Error::PluginExists(manifest.name));
+        }
+        std::fs::rename(&staging, &dir)?;
+
+        let (info, result) = self.open(&dir, &trusted_keys, &HashSet::new()).await;
+        let result = match result {
+            Ok(()) => storage.set_plugin_enabled(&info.name, true).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            self.remove_routes(&info.name);
+            let _ = std::fs::remove_dir_all(&dir);
+            return Err(err);
+        }
+        self.plugins.write().unwrap().push(info.clone());
+        self.run_plugin_hooks(&info.name, HookEvent::Start).await;
+        self.notify(&info.name, PluginEvent::Installed);
+        Ok(info)
+    }
+
+    /// Load a disabled plugin, or a plugin that failed to load, again from
+    /// its directory and run its start hooks
+    pub async fn enable(
+        &self,
+        config: &PluginsConfig,
+        storage: &dyn Storage,
+        name: &str,
+    ) -> Result<PluginInfo, Error> {
+        let _lifecycle = self.lifecycle.lock().await;
+        let current = self.find(name)?;
+        if current.is_loaded() {
+            return Ok(current);
+        }
+        let trusted_keys = config.trusted_keys()?;
+
+        // Out of the list while it loads, so it does not conflict with itself
+        self.plugins
+            .write()
+            .unwrap()
+            .retain(|plugin| plugin.directory != current.directory);
+        let (info, result) = self
+            .open(&current.directory, &trusted_keys, &HashSet::new())
+            .await;
+        let mut info = loaded(info, result);
+        // Only a plugin that loads is enabled again on restart
+        if !current.enabled && info.is_loaded() {
+            if let Err(err) = storage.set_plugin_enabled(name, true).await {
+                self.remove_routes(&info.name);
+                self.plugins.write().unwrap().push(current);
+                return Err(err);
+            }
+        } else if !current.enabled {
+            info.enabled = false;
+        }
+        self.plugins.write().unwrap().push(info.clone());
+        if info.is_loaded() {
+            self.run_plugin_hooks(&info.name, HookEvent::Start).await;
+        }
+        self.notify(&info.name, PluginEvent::Enabled);
+        match &info.error {
+            Some(error) => Err(Error::Plugin(error.clone())),
+            None => Ok(info),
+        }
+    }
+
+    /// Run the stop hooks of the plugin and remove its routes from the
+    /// running servers. The library is released once the calls in progress
+    /// return, and is not loaded again on restart.
+    pub async fn disable(&self, storage: &dyn Storage, name: &str) -> Result<PluginInfo, Error> {
+        let _lifecycle = self.lifecycle.lock().await;
+        let current = self.find(name)?;
+        if !current.enabled {
+            return Ok(current);
+        }
+        storage.set_plugin_enabled(name, false).await?;
+        if current.is_loaded() {
+            self.run_plugin_hooks(name, HookEvent::Stop).await;
+        }
+        self.remove_routes(name);
+        let mut plugins = self.plugins.write().unwrap();
+        let Some(info) = plugins.iter_mut().find(|plugin| plugin.name == name) else {
+            return Err(Error::PluginNotFound(name.to_string()));
+        };
+        info.enabled = false;
+        info.error = None;
+        info.grpc_methods.clear();
+        info.http_routes.clear();
+        let info = info.clone();
+        drop(plugins);
+        self.notify(name, PluginEvent::Disabled);
+        Ok(info)
+    }
+
+    /// Disable the plugin, then remove its directory and its state
+    pub async fn unload(&self, storage: &dyn Storage, name: &str) -> Result<(), Error> {
+        let _lifecycle = self.lifecycle.lock().await;
+        let current = self.find(name)?;
+        storage.delete_plugin(name).await?;
+        if current.is_loaded() {
+            self.run_plugin_hooks(name, HookEvent::Stop).await;
+        }
+        self.remove_routes(name);
+        self.plugins
+            .write()
+            .unwrap()
+            .retain(|plugin| plugin.name != name);
+        if let Err(err) = std::fs::remove_dir_all(&current.directory) {
+            tracing::error!("Unable to remove {}: {}", current.directory.display(), err);
+        }
+        self.notify(name, PluginEvent::Unloaded);
+        Ok(())
+    }
+
+    /// Open the library, check its ABI version and let it register its
+    /// routes and hooks
+    fn open_library(&self, path: &Path) -> Result<Endpoints, Error> {
+        let failed = |err: dlopen2::Error| Error::Plugin(err.to_string());
+        let library = Library::open(path).map_err(failed)?;
+        // SAFETY: the symbols have the types of the ABI, checked through its
+        // version
+        let (register, free_response) = unsafe {
+            let abi_version: AbiVersionFn =
+                library.symbol("lucle_plugin_abi_version").map_err(failed)?;
+            let found = abi_version();
+            if found != ABI_VERSION {
+                return Err(Error::PluginAbiVersion(found, ABI_VERSION));
+            }
+            let register: RegisterFn = library
+                .symbol(&format!("lucle_plugin_register_v{}", ABI_VERSION))
+                .map_err(failed)?;
+            let free_response: FreeResponseFn = library
+                .symbol("lucle_plugin_free_response")
+                .map_err(failed)?;
+            (register, free_response)
+        };
+
+        let mut registration = Registration::default();
+        let registrar = Registrar {
+            context: &mut registration as *mut Registration as *mut c_void,
+            grpc_method: register_grpc_method,
+            http_route: register_http_route,
+            hook: register_hook,
+        };
+        // SAFETY: the registrar and its context outlive the call
+        let code = unsafe { register(&registrar) };
+        if code != 0 {
+            return Err(Error::Plugin(format!("Registration failed with {}", code)));
+        }
+        if !registration.errors.is_empty() {
+            return Err(Error::Plugin(registration.errors.join(", ")));
+        }
+
+        let library = Arc::new(LoadedLibrary {
+            _library: library,
+            free_response,
+        });
+        let endpoint = |handler, user_data| Endpoint::Native {
+            handler,
+            user_data,
+            library: library.clone(),
+        };
+        Ok(Endpoints {
+            grpc: registration
+                .grpc
+                .into_iter()
+                .map(|(path, handler, user_data)| (path, endpoint(handler, user_data)))
+                .collect(),
+            http: registration
+                .http
+                .into_iter()
+                .map(|(method, path, handler, user_data)| {
+                    (method, path, endpoint(handler, user_data))
+                })
+                .collect(),
+            hooks: registration
+                .hooks
+                .into_iter()
+                .map(|(event, hook, user_data)| {
+                    let target = HookTarget::Native {
+                        hook,
+                        user_data,
+                        _library: library.clone(),
+                    };
+                    (event, target)
+                })
+                .collect(),
+        })
+    }
+
+    /// Instantiate the module in its sandbox and let it register its routes
+    /// and hooks
+    async fn open_module(&self, manifest: &Manifest, path: &Path) -> Result<Endpoints, Error> {
+        let (name, path, config) = (
+            manifest.name.clone(),
+            path.to_path_buf(),
+            manifest.config.clone(),
+        );
+        let host = WasmHost {
+            storage: self.storage.clone(),
+            events: self.events.clone(),
+            limits: self.wasm_limits,
+        };
+        let (module, registration) =
+            tokio::task::spawn_blocking(move || WasmModule::load(&name, &path, config, host))
+                .await
+                .map_err(|err| Error::Plugin(err.to_string()))??;
+
+        let module = Arc::new(module);
+        let endpoint = |route| Endpoint::Wasm {
+            module: module.clone(),
+            route,
+        };
+        Ok(Endpoints {
+            grpc: registration
+                .grpc
+                .into_iter()
+                .map(|(path, route)| (path, endpoint(route)))
+                .collect(),
+            http: registration
+                .http
+                .into_iter()
+                .map(|(method, path, route)| (method, path, endpoint(route)))
+                .collect(),
+            hooks: registration
+                .hooks
+                .into_iter()
+                .map(|event| (event, HookTarget::Wasm(module.clone())))
+                .collect(),
+        })
+    }
+
+    /// Add the routes and hooks of the plugin, unless one of its gRPC
+    /// methods belongs to another plugin
+    fn add_endpoints(
+        &self,
+        name: &str,
+        endpoints: Endpoints,
+        info: &mut PluginInfo,
+    ) -> Result<(), Error> {
+        let route = |endpoint| Route {
+            plugin: name.to_string(),
+            endpoint,
+        };
+        let mut grpc = self.grpc.write().unwrap();
+        let mut http = self.http.write().unwrap();
+        for (path, _) in &endpoints.grpc {
+            if grpc.contains_key(path) {
+                return Err(Error::Plugin(format!("{} is already registered", path)));
+            }
+        }
+        for (path, endpoint) in endpoints.grpc {
+            info.grpc_methods.push(path.clone());
+            grpc.insert(path, route(endpoint));
+        }
+        for (method, path, endpoint) in endpoints.http {
+            info.http_routes
+                .push(format!("{} /plugins/{}{}", method, name, path));
+            http.insert((name.to_string(), method, path), route(endpoint));
+        }
+        self.hooks
+            .write()
+            .unwrap()
+            .extend(
+                endpoints
+                    .hooks
+                    .into_iter()
+                    .map(|(event, target)| HookEntry {
+                        plugin: name.to_string(),
+                        event,
+                        target,
+                    }),
+            );
+        Ok(())
+    }
+
+    /// Call the hooks registered for `event`, in the order they were
+    /// registered
+    pub async fn run_hooks(&self, event: HookEvent) {
+        self.call_hooks(event, |_| true).await
+    }
+
+    async fn run_plugin_hooks(&self, plugin: &str, event: HookEvent) {
+        self.call_hooks(event, |entry| entry.plugin == plugin).await
+    }
+
+    async fn call_hooks(&self, event: HookEvent, filter: impl Fn(&HookEntry) -> bool) {
+        let hooks: Vec<HookEntry> = self
+            .hooks
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|entry| entry.event == event as u32 && filter(entry))
+            .cloned()
+            .collect();
+        for entry in hooks {
+            let plugin = entry.plugin.clone();
+            let result = tokio::task::spawn_blocking(move || entry.target.run(entry.event))
+                .await
+                .map_err(|err| Error::Plugin(err.to_string()))
+                .and_then(|result| result);
+            if let Err(err) = result {
+                tracing::error!("Plugin {} {:?} hook failed: {}", plugin, event, err);
+            }
+        }
+    }
+
+    /// Forget the routes and hooks of the plugin, its library is released
+    /// with the last of them
+    fn remove_routes(&self, name: &str) {
+        self.grpc
+            .write()
+            .unwrap()
+            .retain(|_, route| route.plugin != name);
+        self.http
+            .write()
+            .unwrap()
+            .retain(|(plugin, ..), _| plugin != name);
+        self.hooks
+            .write()
+            .unwrap()
+            .retain(|entry| entry.plugin != name);
+    }
+
+    pub fn grpc_route(&self, path: &str) -> Option<Route> {
+        self.grpc.read().unwrap().get(path).cloned()
+    }
+
+    pub fn http_route(&self, plugin: &str, method: &str, path: &str) -> Option<Route> {
+        self.http
+            .read()
+            .unwrap()
+            .get(&(plugin.to_string(), method.to_string(), path.to_string()))
+            .cloned()
+    }
+}
+
+impl Route {
+    /// Call the handler on a blocking thread
+    pub async fn call(
+        &self,
+        method: &str,
+        path: &str,
+        username: Option<&str>,
+        body: Vec<u8>,
+    ) -> Result<PluginReply, Error> {
+        let call = PluginCall {
+            method: method.to_string(),
+            path: path.to_string(),
+            username: username.map(str::to_string),
+            body,
+        };
+        let endpoint = self.endpoint.clone();
+        tokio::task::spawn_blocking(move || endpoint.call(call))
+            .await
+            .map_err(|err| Error::Plugin(format!("Plugin {} failed: {}", self.plugin, err)))?
+    }
+}
+
+impl Endpoint {
+    fn call(&self, call: PluginCall) -> Result<PluginReply, Error> {
+        let (handler, user_data, library) = match self {
+            Endpoint::Native {
+                handler,
+                user_data,
+                library,
+            } => (handler, user_data, library),
+            Endpoint::Wasm { module, route } => return module.call(*route, call),
+        };
+        let invalid = |_| Error::Plugin("Request contains a NUL byte".to_string());
+        let method = CString::new(call.method).map_err(invalid)?;
+        let path = CString::new(call.path).map_err(invalid)?;
+        let username = call
+            .username
+            .map(CString::new)
+            .transpose()
+            .map_err(invalid)?;
+        let request = PluginRequest {
+            method: method.as_ptr(),
+            path: path.as_ptr(),
+            username: username.as_ref().map_or(ptr::null(), |name| name.as_ptr()),
+            body: call.body.as_ptr(),
+            body_len: call.body.len(),
+        };
+        let mut response = PluginResponse {
+            status: 0,
+            content_type: ptr::null_mut(),
+            body: ptr::null_mut(),
+            body_len: 0,
+        };
+        // SAFETY: the library stays loaded while `self` is alive, the request
+        // outlives the call and the response is only read before the plugin
+        // releases it
+        unsafe {
+            handler(user_data.as_ptr(), &request, &mut response);
+            let reply = PluginReply {
+                status: response.status,
+                content_type: (!response.content_type.is_null()).then(|| {
+                    CStr::from_ptr(response.content_type)
+                        .to_string_lossy()
+                        .into_owned()
+                }),
+                body: if response.body.is_null() {
+                    Vec::new()
+                } else {
+                    std::slice::from_raw_parts(response.body, response.body_len).to_vec()
+                },
+            };
+            (library.free_response)(&mut response);
+            Ok(reply)
+        }
+    }
+}
+
+impl HookTarget {
+    fn run(&self, event: u32) -> Result<(), Error> {
+        match self {
+            // SAFETY: the library stays loaded while `self` is alive
+            HookTarget::Native {
+                hook, user_data, ..
+            } => unsafe {
+                hook(user_data.as_ptr(), event);
+                Ok(())
+            },
+            HookTarget::Wasm(module) => module.hook(event),
+        }
+    }
+}
+
+/// Log the outcome of opening a plugin and record its error
+fn loaded(mut info: PluginInfo, result: Result<(), Error>) -> PluginInfo {
+    match result {
+        Ok(()) if !info.enabled => {
+            tracing::info!("Plugin {} {} is disabled", info.name, info.version)
+        }
+        Ok(()) => tracing::info!("Plugin {} {} loaded", info.name, info.version),
+        Err(err) => {
+            tracing::error!("Plugin {} not loaded: {}", info.name, err);
+            info.error = Some(err.to_string());
+        }
+    }
+    info
+}
+
+/// Unpack a gzipped tar archive of files and directories into `dir`,
+/// refusing links, paths leaving `dir` and more than `max_size` bytes
+fn unpack(archive: &[u8], dir: &Path, max_size: u64) -> Result<(), Error> {
+    let invalid = |err: std::io::Error| Error::PluginArchive(err.to_string());
+    let mut archive = tar::Archive::new(GzDecoder::new(archive));
+    let mut size = 0;
+    for entry in archive.entries().map_err(invalid)? {
+        let mut entry = entry.map_err(invalid)?;
+        let path = entry.path().map_err(invalid)?.display().to_string();
+        if !matches!(
+            entry.header().entry_type(),
+            EntryType::Regular | EntryType::Directory
+        ) {
+            return Err(Error::PluginArchive(format!(
+                "{} is not a file or a directory",
+                path
+            )));
+        }
+        size += entry.size();
+        if size > max_size {
+            return Err(Error::UploadTooLarge(
+                "plugin archive".to_string(),
+                max_size,
+            ));
+        }
+        if !entry.unpack_in(dir).map_err(invalid)? {
+            return Err(Error::PluginArchive(format!(
+                "{} is outside of the archive",
+                path
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn read_manifest(dir: &Path) -> Result<Manifest, Error> {
+    let path = dir.join(MANIFEST_FILE);
+    let content = std::fs::read_to_string(&path)?;
+    let manifest: Manifest = toml::from_str(&content)
+        .map_err(|err| Error::PluginManifest(path.display().to_string(), err.to_string()))?;
+    if !crate::speedupdate::is_valid_name(&manifest.name) {
+        return Err(Error::PluginManifest(
+            path.display().to_string(),
+            format!("invalid name `{}`", manifest.name),
+        ));
+    }
+    // Like the name, a file name cannot point out of the plugin directory
+    if !manifest
+        .library
+        .to_str()
+        .is_some_and(crate::speedupdate::is_valid_name)
+    {
+        return Err(Error::PluginManifest(
+            path.display().to_string(),
+            format!("invalid library file name `{}`", manifest.library.display()),
+        ));
+    }
+    if manifest.abi_version != ABI_VERSION {
+        return Err(Error::PluginAbiVersion(manifest.abi_version, ABI_VERSION));
+    }
+    Ok(manifest)
+}
+
+/// Read a C string argument of the registrar
+unsafe fn argument(value: *const c_char) -> Option<String> {
+    if value.is_null() {
+        return None;
+    }
+    CStr::from_ptr(value).to_str().ok().map(str::to_string)
+}
+
+unsafe extern "C" fn register_grpc_method(
+    context: *mut c_void,
+    path: *const c_char,
+    handler: Handler,
+    user_data: *mut c_void,
+) -> i32 {
+    let registration = &mut *(context as *mut Registration);
+    match argument(path) {
+        Some(path) if is_grpc_method(&path) => {
+            registration.grpc.push((path, handler, UserData(user_data)));
+            0
+        }
+        path => {
+            registration
+                .errors
+                .push(format!("Invalid gRPC method {:?}", path));
+            -1
+        }
+    }
+}
+
+unsafe extern "C" fn register_http_route(
+    context: *mut c_void,
+    method: *const c_char,
+    path: *const c_char,
+    handler: Handler,
+    user_data: *mut c_void,
+) -> i32 {
+    let registration = &mut *(context as *mut Registration);
+    match (argument(method), argument(path)) {
+        (Some(method), Some(path)) if is_http_route(&method, &path) => {
+            registration
+                .http
+                .push((method, path, handler, UserData(user_data)));
+            0
+        }
+        (method, path) => {
+            registration
+                .errors
+                .push(format!("Invalid HTTP route {:?} {:?}", method, path));
+            -1
+        }
+    }
+}
+
+unsafe extern "C" fn register_hook(
+    context: *mut c_void,
+    event: u32,
+    hook: Hook,
+    user_data: *mut c_void,
+) -> i32 {
+    let registration = &mut *(context as *mut Registration);
+    if !is_hook_event(event) {
+        registration
+            .errors
+            .push(format!("Unknown hook event {}", event));
+        return -1;
+    }
+    registration.hooks.push((event, hook, UserData(user_data)));
+    0
+}
+
+/// `/<package>.<Service>/<Method>`, outside the services of lucle
+pub(crate) fn is_grpc_method(path: &str) -> bool {
+    let mut parts = path.splitn(3, '/');
+    matches!(
+        (parts.next(), parts.next(), parts.next()),
+        (Some(""), Some(service), Some(method))
+            if service.contains('.') && !method.is_empty() && !method.contains('/')
+    ) && !RESERVED_SERVICES
+        .iter()
+        .any(|reserved| path.starts_with(reserved))
+}
+
+/// Uppercase method and absolute path
+pub(crate) fn is_http_route(method: &str, path: &str) -> bool {
+    path.starts_with('/') && !method.is_empty() && method.bytes().all(|c| c.is_ascii_uppercase())
+}
+
+pub(crate) fn is_hook_event(event: u32) -> bool {
+    event <= HookEvent::Stop as u32
+}
+
+/// Passes the encoded messages through, the plugins decode them
+#[derive(Clone, Copy, Default)]
+struct RawCodec;
+
+impl Codec for RawCodec {
+    type Encode = Vec<u8>;
+    type Decode = Vec<u8>;
+    type Encoder = RawCodec;
+    type Decoder = RawCodec;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        RawCodec
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        RawCodec
+    }
+}
+
+impl Encoder for RawCodec {
+    type Item = Vec<u8>;
+    type Error = Status;
+
+    fn encode(&mut self, item: Vec<u8>, dst: &mut EncodeBuf<'_>) -> Result<(), Status> {
+        dst.put_slice(&item);
+        Ok(())
+    }
+}
+
+impl Decoder for RawCodec {
+    type Item = Vec<u8>;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<Vec<u8>>, Status> {
+        let mut message = vec![0; src.remaining()];
+        src.copy_to_slice(&mut message);
+        Ok(Some(message))
+    }
+}
+
+/// Fallback of the gRPC router, calling the plugin that registered the
+/// method
+pub async fn grpc_fallback(host: Arc<PluginHost>, request: HttpRequest) -> HttpResponse {
+    let path = request.uri().path().to_string();
+    let mut grpc = tonic::server::Grpc::new(RawCodec);
+    let Some(route) = host.grpc_route(&path) else {
+        return grpc
+            .unary(
+                service_fn(|_: tonic::Request<Vec<u8>>| async {
+                    Err::<tonic::Response<Vec<u8>>, _>(Status::unimplemented("Unknown method"))
+                }),
+                request,
+            )
+            .await
+            .into_response();
+    };
+    let service = service_fn(move |request: tonic::Request<Vec<u8>>| {
+        let (route, path) = (route.clone(), path.clone());
+        async move {
+            let username = Identity::from_request(&request).ok().map(|id| id.username);
+            let reply = route
+                .call("POST", &path, username.as_deref(), request.into_inner())
+                .await
+                .map_err(|err| {
+                    tracing::error!("{}", err);
+                    Status::internal(err.to_string())
+                })?;
+            if reply.status == 0 {
+                Ok(tonic::Response::new(reply.body))
+            } else {
+                Err(Status::new(
+                    Code::from(reply.status as i32),
+                    String::from_utf8_lossy(&reply.body),
+                ))
+            }
+        }
+    });
+    grpc.unary(service, request).await.into_response()
+}
+
+/// Serve `/plugins/:plugin/*path` with the plugin routes
+pub async fn http_handler(
+    host: Arc<PluginHost>,
+    RoutePath((plugin, path)): RoutePath<(String, String)>,
+    request: HttpRequest,
+) -> HttpResponse {
+    let path = format!("/{}", path);
+    let method = request.method().to_string();
+    let Some(route) = host.http_route(&plugin, &method, &path) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let body = match axum::body::to_bytes(request.into_body(), MAX_BODY_SIZE).await {
+        Ok(body) => body.to_vec(),
+        Err(_) => return StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+    };
+    match route.call(&method, &path, None, body).await {
+        Ok(reply) => {
+            let status = u16::try_from(reply.status)
+                .ok()
+                .and_then(|status| StatusCode::from_u16(status).ok())
+                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            let mut response = HttpResponse::new(Body::from(reply.body));
+            *response.status_mut() = status;
+            if let Some(value) = reply
+                .content_type
+                .and_then(|content_type| content_type.parse().ok())
+            {
+                response.headers_mut().insert(header::CONTENT_TYPE, value);
+            }
+            response
+        }
+        Err(err) => {
+            tracing::error!("{}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diesel::tests::sqlite_storage;
+    use flate2::{write::GzEncoder, Compression};
+
+    const MANIFEST: &str =
+        "name = \"echo\"\nversion = \"1.0.0\"\nabi_version = 1\nlibrary = \"echo.wasm\"\n";
+
+    /// Empty directory in the temporary directory, `name` is unique per test
+    fn directory(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lucle-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Header of an entry at `path`, written as is so that it can leave the
+    /// archive. Links point at `plugin.toml`.
+    fn header(path: &str, entry_type: EntryType, size: u64) -> tar::Header {
+        let mut header = tar::Header::new_old();
+        header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_entry_type(entry_type);
+        header.set_size(size);
+        header.set_mode(0o644);
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            header.set_link_name(MANIFEST_FILE).unwrap();
+        }
+        header.set_cksum();
+        header
+    }
+
+    fn archive(entries: &[(&str, EntryType, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (path, entry_type, data) in entries {
+            builder
+                .append(&header(path, *entry_type, data.len() as u64), *data)
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    /// Archive of a WebAssembly plugin registering `grpc_method` and
+    /// `GET /status`, signed by a new key, and the plugin configuration
+    /// trusting that key
+    fn wasm_plugin(name: &str, grpc_method: &str) -> (Vec<u8>, PluginsConfig) {
+        let module = wat::parse_str(format!(
+            r#"(module
+                (import "lucle" "grpc_method" (func $grpc_method (param i32 i32) (result i32)))
+                (import "lucle" "http_route" (func $http_route (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "GET")
+                (data (i32.const 16) "/status")
+                (data (i32.const 32) "{grpc_method}")
+                (func (export "lucle_plugin_abi_version") (result i32) (i32.const {ABI_VERSION}))
+                (func (export "lucle_plugin_register_v1") (result i32)
+                    (drop (call $grpc_method (i32.const 32) (i32.const {})))
+                    (drop (call $http_route (i32.const 0) (i32.const 3) (i32.const 16) (i32.const 7)))
+                    (i32.const 0))
+                (func (export "lucle_plugin_handle_v1") (param i32) (result i32) (i32.const 200)))"#,
+            grpc_method.len()
+        ))
+        .unwrap();
+        let keypair = minisign::KeyPair::generate_unencrypted_keypair().unwrap();
+        let signature = minisign::sign(Some(&keypair.pk), &keypair.sk, &*module, None, None)
+            .unwrap()
+            .into_string();
+        let archive = archive(&[
+            (
+                MANIFEST_FILE,
+                EntryType::Regular,
+                (MANIFEST.to_string() + "runtime = \"wasm\"\n").as_bytes(),
+            ),
+            ("echo.wasm", EntryType::Regular, &module),
+            (
+                "echo.wasm.minisig",
+                EntryType::Regular,
+                signature.as_bytes(),
+            ),
+        ]);
+        let config = PluginsConfig {
+            directory: directory(name),
+            trusted_keys: vec![keypair.pk.to_base64()],
+            ..PluginsConfig::default()
+        };
+        (archive, config)
+    }
+
+    fn manifest(dir: &Path, content: &str) -> Result<Manifest, Error> {
+        std::fs::write(dir.join(MANIFEST_FILE), content).unwrap();
+        read_manifest(dir)
+    }
+
+    #[test]
+    fn archive_is_unpacked() {
+        let dir = directory("plugin-unpack");
+        let archive = archive(&[
+            (MANIFEST_FILE, EntryType::Regular, MANIFEST.as_bytes()),
+            ("echo.wasm", EntryType::Regular, b"\0asm"),
+        ]);
+        unpack(&archive, &dir, 1024).unwrap();
+        assert_eq!(std::fs::read(dir.join("echo.wasm")).unwrap(), b"\0asm");
+        let manifest = read_manifest(&dir).unwrap();
+        assert_eq!(
+            (manifest.name.as_str(), manifest.runtime),
+            ("echo", Runtime::Native)
+        );
+    }
+
+    #[test]
+    fn links_are_refused() {
+        for entry_type in [EntryType::Symlink, EntryType::Link] {
+            let dir = directory("plugin-link");
+            let archive = archive(&[
+                (MANIFEST_FILE, EntryType::Regular, MANIFEST.as_bytes()),
+                ("echo.wasm", entry_type, b""),
+            ]);
+            let result = unpack(&archive, &dir, 1024);
+            assert!(
+                matches!(result, Err(Error::PluginArchive(_))),
+                "{:?}",
+                entry_type
+            );
+            assert!(std::fs::symlink_metadata(dir.join("echo.wasm")).is_err());
+        }
+    }
+
+    #[test]
+    fn paths_out_of_the_archive_are_refused() {
+        let parent = directory("plugin-escape");
+        let dir = parent.join("staging");
+        std::fs::create_dir(&dir).unwrap();
+        let archive = archive(&[("../escaped", EntryType::Regular, b"data")]);
+        let result = unpack(&archive, &dir, 1024);
+        assert!(matches!(result, Err(Error::PluginArchive(_))));
+        assert!(!parent.join("escaped").exists());
+    }
+
+    #[test]
+    fn archives_over_the_size_limit_are_refused() {
+        let dir = directory("plugin-size");
+        let data = [0; 600];
+        let archive = archive(&[
+            ("a", EntryType::Regular, &data),
+            ("b", EntryType::Regular, &data),
+        ]);
+        let result = unpack(&archive, &dir, 1000);
+        assert!(matches!(result, Err(Error::UploadTooLarge(_, 1000))));
+        assert!(!dir.join("b").exists());
+    }
+
+    #[test]
+    fn invalid_manifests_are_refused() {
+        let dir = directory("plugin-manifest");
+        for library in [
+            "../echo.wasm",
+            "lib/echo.wasm",
+            "/tmp/echo.wasm",
+            ".echo.wasm",
+        ] {
+            let content = MANIFEST.replace("echo.wasm", library);
+            let result = manifest(&dir, &content);
+            assert!(
+                matches!(result, Err(Error::PluginManifest(..))),
+                "{}",
+                library
+            );
+        }
+        let result = manifest(&dir, &MANIFEST.replace("\"echo\"", "\"../echo\""));
+        assert!(matches!(result, Err(Error::PluginManifest(..))));
+        let result = manifest(
+            &dir,
+            &MANIFEST.replace("abi_version = 1", "abi_version = 2"),
+        );
+        assert!(matches!(
+            result,
+            Err(Error::PluginAbiVersion(2, ABI_VERSION))
+        ));
+    }
+
+    #[test]
+    fn services_of_lucle_are_reserved() {
+        assert!(is_grpc_method("/echo.Echo/Say"));
+        for path in [
+            "/luclerpc.Lucle/Login",
+            "/speedupdate.Repo/Status",
+            "/echo/Say",
+            "/echo.Echo/",
+            "/echo.Echo/Say/more",
+            "echo.Echo/Say",
+        ] {
+            assert!(!is_grpc_method(path), "{}", path);
+        }
+        assert!(is_http_route("GET", "/status"));
+        for (method, path) in [("get", "/status"), ("", "/status"), ("GET", "status")] {
+            assert!(!is_http_route(method, path), "{} {}", method, path);
+        }
+    }
+
+    #[test]
+    fn native_plugins_cannot_register_reserved_methods() {
+        unsafe extern "C" fn handler(
+            _: *mut c_void,
+            _: *const PluginRequest,
+            _: *mut PluginResponse,
+        ) {
+        }
+        let mut registration = Registration::default();
+        let context = &mut registration as *mut Registration as *mut c_void;
+        // SAFETY: the context is a registration and the paths are C strings
+        let codes = unsafe {
+            [
+                register_grpc_method(
+                    context,
+                    c"/echo.Echo/Say".as_ptr(),
+                    handler,
+                    ptr::null_mut(),
+                ),
+                register_grpc_method(
+                    context,
+                    c"/luclerpc.Lucle/Login".as_ptr(),
+                    handler,
+                    ptr::null_mut(),
+                ),
+                register_http_route(
+                    context,
+                    c"get".as_ptr(),
+                    c"/".as_ptr(),
+                    handler,
+                    ptr::null_mut(),
+                ),
+            ]
+        };
+        assert_eq!(codes, [0, -1, -1]);
+        assert_eq!(registration.grpc.len(), 1);
+        assert_eq!(registration.errors.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn disabled_plugin_routes_are_removed() {
+        let (archive, config) = wasm_plugin("plugin-disable", "/echo.Echo/Say");
+        let storage = Arc::new(sqlite_storage("plugin-disable").await);
+        let host = PluginHost::default();
+        let info = host.install(&config, &*storage, archive).await.unwrap();
+        assert_eq!(info.grpc_methods, ["/echo.Echo/Say"]);
+        assert!(host.grpc_route("/echo.Echo/Say").is_some());
+        assert!(host.http_route("echo", "GET", "/status").is_some());
+
+        let info = host.disable(&*storage, "echo").await.unwrap();
+        assert!(!info.enabled && info.grpc_methods.is_empty());
+        assert!(host.grpc_route("/echo.Echo/Say").is_none());
+        assert!(host.http_route("echo", "GET", "/status").is_none());
+        let plugins = storage.list_plugins().await.unwrap();
+        assert!(plugins
+            .iter()
+            .any(|plugin| plugin.name == "echo" && !plugin.enabled));
+
+        // Not loaded again on restart
+        let host = PluginHost::load(&config, SharedStorage::new(Some(storage))).await;
+        assert!(host.list().iter().all(|plugin| !plugin.enabled));
+        assert!(host.grpc_route("/echo.Echo/Say").is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_install_leaves_no_files() {
+        let storage = sqlite_storage("plugin-install").await;
+        let host = PluginHost::default();
+        let failed = |config: &PluginsConfig| {
+            !config.directory.join(".staging").exists() && !config.directory.join("echo").exists()
+        };
+
+        // Refused while unpacking
+        let (_, config) = wasm_plugin("plugin-install-link", "/echo.Echo/Say");
+        let archive = archive(&[(MANIFEST_FILE, EntryType::Symlink, b"")]);
+        assert!(host.install(&config, &storage, archive).await.is_err());
+        assert!(failed(&config));
+
+        // Refused once moved to its directory
+        let (archive, config) = wasm_plugin("plugin-install-untrusted", "/echo.Echo/Say");
+        let untrusted = PluginsConfig {
+            trusted_keys: Vec::new(),
+            ..config
+        };
+        let result = host.install(&untrusted, &storage, archive).await;
+        assert!(matches!(result, Err(Error::UntrustedSignature(_))));
+        assert!(failed(&untrusted));
+
+        let (archive, config) = wasm_plugin("plugin-install-reserved", "/luclerpc.Lucle/Login");
+        let result = host.install(&config, &storage, archive).await;
+        assert!(matches!(result, Err(Error::Plugin(_))));
+        assert!(failed(&config));
+        assert!(host.list().is_empty());
+        assert!(storage.list_plugins().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,651 @@
+use super::auth::Identity;
+use super::config::LucleConfig;
+use super::errors::Error;
+use super::models::Permission;
+use super::speedupdate::{RepositoryStatus, UpdateRepository};
+use super::storage::SharedStorage;
+use super::user;
+use speedupdaterpc::{
+    repo_server::Repo, Empty, FileToDelete, FileToUpload, Package, Progress, RepoStatus,
+    RepositoryPath, TrustedKey, TrustedKeys, UploadChunk, UploadStatus, Version, Versions,
+};
+use std::collections::{HashMap, HashSet};
+use std::pin::Pin;
+use std::sync::{self, Arc};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc, Mutex, OwnedMutexGuard,
+};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use tonic::{Request, Response, Status, Streaming};
+
+pub mod speedupdaterpc {
+    tonic::include_proto!("speedupdate");
+}
+
+type StatusStream = Pin<Box<dyn Stream<Item = Result<RepoStatus, Status>> + Send>>;
+
+/// Sent to the status streams of a repository
+#[derive(Clone)]
+enum RepoEvent {
+    Changed,
+    Progress(Progress),
+}
+
+/// Speedupdate `Repo` service. Every path is the name of a registered
+/// repository, members read it and members with write access publish to it.
+pub struct RepoApi {
+    storage: SharedStorage,
+    config: Arc<LucleConfig>,
+    /// Serialize the changes to the files of each repository
+    locks: sync::Mutex<HashMap<String, Arc<Mutex<()>>>>,
+    /// Events of every repository, tagged with its name
+    events: broadcast::Sender<(String, RepoEvent)>,
+    /// Repository and file of the uploads in progress
+    uploads: Arc<sync::Mutex<HashSet<(String, String)>>>,
+}
+
+/// Removes an upload from the ones in progress when dropped
+struct UploadGuard {
+    uploads: Arc<sync::Mutex<HashSet<(String, String)>>>,
+    key: (String, String),
+}
+
+impl Drop for UploadGuard {
+    fn drop(&mut self) {
+        self.uploads.lock().unwrap().remove(&self.key);
+    }
+}
+
+impl RepoApi {
+    pub fn new(storage: SharedStorage, config: Arc<LucleConfig>) -> Self {
+        Self {
+            storage,
+            config,
+            locks: sync::Mutex::default(),
+            events: broadcast::channel(64).0,
+            uploads: Arc::default(),
+        }
+    }
+
+    /// Wait until no other change is made to the files of the repository at
+    /// `path`
+    async fn lock(&self, path: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+            // Forget the locks nobody holds or waits for
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry(path.to_string()).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+
+    /// A file is uploaded by a single stream at a time
+    fn start_upload(&self, path: &str, file: &str) -> Result<UploadGuard, Error> {
+        let key = (path.to_string(), file.to_string());
+        if !self.uploads.lock().unwrap().insert(key.clone()) {
+            return Err(Error::UploadInProgress(file.to_string()));
+        }
+        Ok(UploadGuard {
+            uploads: self.uploads.clone(),
+            key,
+        })
+    }
+
+    fn notify(&self, path: &str, event: RepoEvent) {
+        // Fails when no status stream is open
+        let _ = self.events.send((path.to_string(), event));
+    }
+
+    fn progress<'a>(
+        &'a self,
+        path: &'a str,
+        operation: &'a str,
+        file: &'a str,
+    ) -> impl FnMut(u64, u64) + Send + 'a {
+        move |done, total| {
+            self.notify(
+                path,
+                RepoEvent::Progress(Progress {
+                    operation: operation.to_string(),
+                    file: file.to_string(),
+                    done,
+                    total,
+                }),
+            )
+        }
+    }
+
+    async fn open(
+        &self,
+        identity: &Identity,
+        path: &str,
+        permission: Permission,
+    ) -> Result<UpdateRepository, Error> {
+        let repository = UpdateRepository::open(&self.config.paths.repositories, path)?;
+        user::require_access(&*self.storage.get()?, &identity.username, path, permission).await?;
+        Ok(repository)
+    }
+
+    /// Open a repository for its owner or an admin
+    async fn open_as_owner(
+        &self,
+        identity: &Identity,
+        path: &str,
+    ) -> Result<UpdateRepository, Error> {
+        let repository = UpdateRepository::open(&self.config.paths.repositories, path)?;
+        user::require_repository_owner(&*self.storage.get()?, &identity.username, path).await?;
+        Ok(repository)
+    }
+}
+
+impl From<RepositoryStatus> for RepoStatus {
+    fn from(status: RepositoryStatus) -> Self {
+        RepoStatus {
+            current_version: status.current_version.unwrap_or_default(),
+            versions: status
+                .versions
+                .into_iter()
+                .map(|version| Versions {
+                    revision: version.revision,
+                    description: version.description,
+                    signer: version.signer.unwrap_or_default(),
+                })
+                .collect(),
+            packages: status.packages,
+            available_packages: status.available_packages,
+            available_binaries: status.available_binaries,
+            size: status.size,
+            progress: None,
+        }
+    }
+}
+
+fn failed(err: Error) -> Status {
+    tracing::error!("{}", err);
+    err.into()
+}
+
+#[tonic::async_trait]
+impl Repo for RepoApi {
+    type StatusStream = StatusStream;
+
+    async fn init(&self, request: Request<RepositoryPath>) -> Result<Response<Empty>, Status> {
+        let identity = Identity::from_request(&request)?;
+        let path = request.into_inner().path;
+        let _guard = self.lock(&path).await;
+        async {
+            self.open(&identity, &path, Permission::Write)
+                .await?
+                .init()
+                .await?;
+            self.notify(&path, RepoEvent::Changed);
+            tracing::info!("{} initialized {} repository", identity.username, path);
+            Ok(())
+        }
+        .await
+        .map(|()| Response::new(Empty {}))
+        .map_err(failed)
+    }
+
+    /// Fails with `failed_precondition` when the repository is not
+    /// initialized
+    async fn is_init(&self, request: Request<RepositoryPath>) -> Result<Response<Empty>, Status> {
+        let identity = Identity::from_request(&request)?;
+        let path = request.into_inner().path;
+        async {
+            if self
+                .open(&identity, &path, Permission::Read)
+                .await?
+                .is_init()
+                .await
+            {
+                Ok(())
+            } else {
+                Err(Error::RepositoryNotInitialized(path.clone()))
+            }
+        }
+        .await
+        .map(|()| Response::new(Empty {}))
+        .map_err(failed)
+    }
+
+    /// Send the status, then a new one after every change. Progress events
+    /// carry the last status. The access of the caller is checked again
+    /// before each event, the stream ends with an error once it is lost.
+    async fn status(
+        &self,
+        request: Request<RepositoryPath>,
+    ) -> Result<Response<Self::StatusStream>, Status> {
+        let identity = Identity::from_request(&request)?;
+        let path = request.into_inner().path;
+        let repository = self
+            .open(&identity, &path, Permission::Read)
+            .await
+            .map_err(failed)?;
+        // Subscribe first so that no change is missed
+        let mut events = self.events.subscribe();
+        let mut last = RepoStatus::from(repository.status().await.map_err(failed)?);
+
+        let storage = self.storage.clone();
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            if tx.send(Ok(last.clone())).await.is_err() {
+                return;
+            }
+            loop {
+                let received = tokio::select! {
+                    received = events.recv() => received,
+                    _ = tx.closed() => break,
+                };
+                let event = match received {
+                    Ok((name, event)) if name == path => event,
+                    Ok(_) => continue,
+                    // Events were dropped, the status is sent again
+                    Err(RecvError::Lagged(_)) => RepoEvent::Changed,
+                    Err(RecvError::Closed) => break,
+                };
+                let access = async {
+                    let storage = storage.get()?;
+                    user::require_access(&*storage, &identity.username, &path, Permission::Read)
+                        .await
+                }
+                .await;
+                if let Err(err) = access {
+                    let _ = tx.send(Err(failed(err))).await;
+                    break;
+                }
+                let message = match event {
+                    RepoEvent::Changed => match repository.status().await {
+                        Ok(status) => {
+                            last = status.into();
+                            Ok(last.clone())
+                        }
+                        Err(err) => Err(failed(err)),
+                    },
+                    RepoEvent::Progress(progress) => Ok(RepoStatus {
+                        progress: Some(progress),
+                        ..last.clone()
+                    }),
+                };
+                let end = message.is_err();
+                if tx.send(message).await.is_err() || end {
+                    break;
+                }
+            }
+            tracing::debug!("{} status stream ended", path);
+        });
+
+        Ok(Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::StatusStream
+        ))
+    }
+
+    async fn set_current_version(
+        &self,
+        request: Request<Version>,
+    ) -> Result<Response<Empty>, Status> {
+        let identity = Identity::from_request(&request)?;
+        let inner = request.into_inner();
+        let _guard = self.lock(&inner.path).await;
+        async {
+            self.open(&identity, &inner.path, Permission::Write)
+                .await?
+                .set_current_version(&inner.version)
+                .await?;
+            self.notify(&inner.path, RepoEvent::Changed);
+            tracing::info!(
+                "{} set {} current version to {}",
+                identity.username,
+                inner.path,
+                inner.version
+            );
+            Ok(())
+        }
+        .await
+        .map(|()| Response::new(Empty {}))
+        .map_err(failed)
+    }
+
+    async fn register_version(&self, request: Request<Version>) -> Result<Response<Empty>, Status> {
+        let identity = Identity::from_request(&request)?;
+        let inner = request.into_inner();
+        let _guard = self.lock(&inner.path).await;
+        async {
+            self.open(&identity, &inner.path, Permission::Write)
+                .await?
+                .register_version(&inner.version, inner.description.as_deref().unwrap_or(""))
+                .await?;
+            self.notify(&inner.path, RepoEvent::Changed);
+            Ok(())
+        }
+        .await
+        .map(|()| Response::new(Empty {}))
+        .map_err(failed)
+    }
+
+    async fn unregister_version(
+        &self,
+        request: Request<Version>,
+    ) -> Result<Response<Empty>, Status> {
+        let identity = Identity::from_request(&request)?;
+        let inner = request.into_inner();
+        let _guard = self.lock(&inner.path).await;
+        async {
+            self.open(&identity, &inner.path, Permission::Write)
+                .await?
+                .unregister_version(&inner.version)
+                .await?;
+            self.notify(&inner.path, RepoEvent::Changed);
+            Ok(())
+        }
+        .await
+        .map(|()| Response::new(Empty {}))
+        .map_err(failed)
+    }
+
+    async fn register_package(&self, request: Request<Package>) -> Result<Response<Empty>, Status> {
+        let identity = Identity::from_request(&request)?;
+        let inner = request.into_inner();
+        let _guard = self.lock(&inner.path).await;
+        async {
+            let repository = self.open(&identity, &inner.path, Permission::Write).await?;
+            let result = repository
+                .register_package(
+                    &inner.name,
+                    self.progress(&inner.path, "register_package", &inner.name),
+                )
+                .await;
+            // Also sent on failure, to end the progress
+            self.notify(&inner.path, RepoEvent::Changed);
+            result
+        }
+        .await
+        .map(|()| Response::new(Empty {}))
+        .map_err(failed)
+    }
+
+    async fn unregister_package(
+        &self,
+        request: Request<Package>,
+    ) -> Result<Response<Empty>, Status> {
+        let identity = Identity::from_request(&request)?;
+        let inner = request.into_inner();
+        let _guard = self.lock(&inner.path).await;
+        async {
+            self.open(&identity, &inner.path, Permission::Write)
+                .await?
+                .unregister_package(&inner.name)
+                .await?;
+            self.notify(&inner.path, RepoEvent::Changed);
+            Ok(())
+        }
+        .await
+        .map(|()| Response::new(Empty {}))
+        .map_err(failed)
+    }
+
+    async fn delete_file(&self, request: Request<FileToDelete>) -> Result<Response<Empty>, Status> {
+        let identity = Identity::from_request(&request)?;
+        let inner = request.into_inner();
+        let _guard = self.lock(&inner.path).await;
+        async {
+            let repository = self.open(&identity, &inner.path, Permission::Write).await?;
+            let result = repository
+                .delete_file(
+                    &inner.file,
+                    self.progress(&inner.path, "delete_file", &inner.file),
+                )
+                .await;
+            // Also sent on failure, to end the progress
+            self.notify(&inner.path, RepoEvent::Changed);
+            result?;
+            tracing::info!(
+                "{} deleted {} from {} repository",
+                identity.username,
+                inner.file,
+                inner.path
+            );
+            Ok(())
+        }
+        .await
+        .map(|()| Response::new(Empty {}))
+        .map_err(failed)
+    }
+
+    async fn upload(
+        &self,
+        request: Request<Streaming<UploadChunk>>,
+    ) -> Result<Response<UploadStatus>, Status> {
+        let identity = Identity::from_request(&request)?;
+        let mut chunks = request.into_inner();
+        let Some(first) = chunks.message().await? else {
+            return Err(Status::invalid_argument("Empty upload"));
+        };
+        let (path, file) = (first.path.clone(), first.file.clone());
+        async {
+            let repository = self.open(&identity, &path, Permission::Write).await?;
+            let _guard = self.start_upload(&path, &file)?;
+            let mut upload = repository
+                .upload(
+                    &file,
+                    first.size,
+                    &first.sha256,
+                    self.config.upload.max_file_size,
+                )
+                .await?;
+            let mut progress = self.progress(&path, "upload", &file);
+            let mut chunk = first;
+            loop {
+                upload.write(chunk.offset, &chunk.data).await?;
+                progress(upload.received(), upload.size());
+                chunk = match chunks.message().await {
+                    Ok(Some(chunk)) => chunk,
+                    Ok(None) => break,
+                    // The received chunks stay staged for the upload to resume
+                    Err(err) => {
+                        tracing::warn!("Upload of {} to {} interrupted: {}", file, path, err);
+                        break;
+                    }
+                };
+            }
+
+            let received = upload.received();
+            let complete = {
+                let _lock = self.lock(&path).await;
+                let complete = upload.finish().await;
+                self.notify(&path, RepoEvent::Changed);
+                complete?
+            };
+            if complete {
+                tracing::info!("{} uploaded {} to {}", identity.username, file, path);
+            }
+            Ok(UploadStatus {
+                file: file.clone(),
+                received,
+                complete,
+            })
+        }
+        .await
+        .map(Response::new)
+        .map_err(failed)
+    }
+
+    async fn upload_status(
+        &self,
+        request: Request<FileToUpload>,
+    ) -> Result<Response<UploadStatus>, Status> {
+        let identity = Identity::from_request(&request)?;
+        let inner = request.into_inner();
+        async {
+            let (received, complete) = self
+                .open(&identity, &inner.path, Permission::Write)
+                .await?
+                .uploaded(&inner.file)
+                .await?;
+            Ok(UploadStatus {
+                file: inner.file.clone(),
+                received,
+                complete,
+            })
+        }
+        .await
+        .map(Response::new)
+        .map_err(failed)
+    }
+
+    async fn add_trusted_key(
+        &self,
+        request: Request<TrustedKey>,
+    ) -> Result<Response<TrustedKey>, Status> {
+        let identity = Identity::from_request(&request)?;
+        let inner = request.into_inner();
+        let _guard = self.lock(&inner.path).await;
+        async {
+            let key = self
+                .open_as_owner(&identity, &inner.path)
+                .await?
+                .add_trusted_key(&inner.public_key)
+                .await?;
+            tracing::info!(
+                "{} trusted key {} for {} repository",
+                identity.username,
+                key.id,
+                inner.path
+            );
+            Ok(TrustedKey {
+                path: inner.path.clone(),
+                public_key: key.public_key,
+                id: key.id,
+            })
+        }
+        .await
+        .map(Response::new)
+        .map_err(failed)
+    }
+
+    async fn remove_trusted_key(
+        &self,
+        request: Request<TrustedKey>,
+    ) -> Result<Response<Empty>, Status> {
+        let identity = Identity::from_request(&request)?;
+        let inner = request.into_inner();
+        let _guard = self.lock(&inner.path).await;
+        async {
+            self.open_as_owner(&identity, &inner.path)
+                .await?
+                .remove_trusted_key(&inner.id)
+                .await?;
+            tracing::info!(
+                "{} removed key {} from {} repository",
+                identity.username,
+                inner.id,
+                inner.path
+            );
+            Ok(())
+        }
+        .await
+        .map(|()| Response::new(Empty {}))
+        .map_err(failed)
+    }
+
+    async fn list_trusted_keys(
+        &self,
+        request: Request<RepositoryPath>,
+    ) -> Result<Response<TrustedKeys>, Status> {
+        let identity = Identity::from_request(&request)?;
+        let path = request.into_inner().path;
+        async {
+            let keys = self
+                .open(&identity, &path, Permission::Read)
+                .await?
+                .trusted_keys()
+                .await?;
+            Ok(TrustedKeys {
+                keys: keys
+                    .into_iter()
+                    .map(|key| TrustedKey {
+                        path: path.clone(),
+                        public_key: key.public_key,
+                        id: key.id,
+                    })
+                    .collect(),
+            })
+        }
+        .await
+        .map(Response::new)
+        .map_err(failed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PathsConfig;
+    use crate::diesel::tests::sqlite_storage;
+    use crate::models::{Role, UsersRepositories};
+    use crate::storage::Storage;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn status_stream_ends_with_the_access() {
+        let storage = Arc::new(sqlite_storage("repo-status-access").await);
+        for username in ["alice", "bob"] {
+            user::create_user(
+                &*storage,
+                username.to_string(),
+                "correct horse battery".to_string(),
+                format!("{}@example.com", username),
+                Role::Member,
+                "en".to_string(),
+            )
+            .await
+            .unwrap();
+        }
+        user::register_update_server(&*storage, "alice".to_string(), "game".to_string())
+            .await
+            .unwrap();
+        let bob = storage.get_user_by_username("bob").await.unwrap().unwrap();
+        storage
+            .add_member(UsersRepositories {
+                user_id: bob.id,
+                repository_name: "game".to_string(),
+                permission: Permission::Read,
+            })
+            .await
+            .unwrap();
+        let root =
+            std::env::temp_dir().join(format!("lucle-repo-status-access-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        UpdateRepository::open(&root, "game")
+            .unwrap()
+            .init()
+            .await
+            .unwrap();
+        let config = LucleConfig {
+            paths: PathsConfig {
+                repositories: root,
+                ..PathsConfig::default()
+            },
+            ..LucleConfig::default()
+        };
+        let api = RepoApi::new(SharedStorage::new(Some(storage.clone())), Arc::new(config));
+
+        let mut request = Request::new(RepositoryPath {
+            path: "game".to_string(),
+        });
+        request.extensions_mut().insert(Identity {
+            username: "bob".to_string(),
+        });
+        let mut stream = api.status(request).await.unwrap().into_inner();
+        assert!(stream.next().await.unwrap().is_ok());
+        api.notify("game", RepoEvent::Changed);
+        assert!(stream.next().await.unwrap().is_ok());
+
+        storage.remove_member(bob.id, "game").await.unwrap();
+        api.notify("game", RepoEvent::Changed);
+        let status = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert!(stream.next().await.is_none());
+    }
+}
@@ -1,16 +1,28 @@
+use super::auth::{AuthLayer, Identity};
+use super::config::{DatabaseConfig, LucleConfig};
 use super::diesel;
-use super::surrealdb;
+use super::jwt::JwtKeys;
+use super::mailer::Mailer;
+use super::models::Role;
+use super::page::{self, PageContent, PageInfo, PageSave, RevisionInfo};
+use super::plugin::{self, PluginEvent, PluginHost, PluginInfo};
+use super::repo::{speedupdaterpc::repo_server::RepoServer, RepoApi};
+use super::storage::{self, SharedStorage, Storage};
 use super::user;
 use crate::DbType;
 use email_address_parser::EmailAddress;
 use luclerpc::{
     lucle_server::{Lucle, LucleServer},
-    Credentials, Database, DatabaseType, Empty, ListUpdateServer, Message, ResetPassword,
-    UpdateServer, User, UserCreation, Username,
+    ConfirmPasswordReset, Credentials, Database, DatabaseType, Empty, ListUpdateServer, Member,
+    Message, Page, PagePatch, PageRevision, PageRevisionDiff, PageRevisions, PageRollback,
+    PageSlug, PageUpdate, PageUpdateResult, Pages, PendingMembers, Plugin, PluginArchive,
+    PluginChange, PluginName, Plugins, RefreshToken, RenameRepository, RepositoryInfo,
+    ResetPassword, Tokens, TransferRepository, UpdateServer, User, UserCreation, Username,
 };
 use std::pin::Pin;
+use std::sync::Arc;
 use std::{error::Error, fs::File, io::BufReader, io::ErrorKind};
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast::error::RecvError, mpsc, watch};
 use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
 use tonic::{
     service::RoutesBuilder, transport::server::Server, Request, Response, Status, Streaming,
@@ -39,81 +51,251 @@ fn match_for_io_error(err_status: &Status) -> Option<&std::io::Error> {
             }
         }
 
-        err = match err.source() {
-            Some(err) => err,
-            None => return None,
-        };
+        err = err.source()?;
+    }
+}
+
+impl From<PluginInfo> for Plugin {
+    fn from(plugin: PluginInfo) -> Self {
+        Plugin {
+            loaded: plugin.is_loaded(),
+            name: plugin.name,
+            version: plugin.version,
+            description: plugin.description,
+            error: plugin.error.unwrap_or_default(),
+            grpc_methods: plugin.grpc_methods,
+            http_routes: plugin.http_routes,
+            enabled: plugin.enabled,
+        }
     }
 }
 
-#[derive(Default)]
-pub struct LucleApi {}
+impl From<PageInfo> for Page {
+    fn from(info: PageInfo) -> Self {
+        let page = info.page;
+        Page {
+            slug: page.slug,
+            title: page.title,
+            body: page.body,
+            status: page.status.as_str().to_string(),
+            author: info.author.unwrap_or_default(),
+            created_at: page.created_at.and_utc().to_rfc3339(),
+            modified_at: page.modified_at.and_utc().to_rfc3339(),
+            published_at: page
+                .published_at
+                .map(|published_at| published_at.and_utc().to_rfc3339())
+                .unwrap_or_default(),
+            revision: page.revision,
+        }
+    }
+}
+
+impl From<PageSave> for PageUpdateResult {
+    fn from(save: PageSave) -> Self {
+        match save {
+            PageSave::Saved(page) => PageUpdateResult {
+                page: Some(page.into()),
+                conflicts: String::new(),
+            },
+            PageSave::Conflict { current, conflicts } => PageUpdateResult {
+                page: Some(current.into()),
+                conflicts,
+            },
+        }
+    }
+}
+
+impl From<RevisionInfo> for PageRevision {
+    fn from(info: RevisionInfo) -> Self {
+        let revision = info.revision;
+        PageRevision {
+            revision: revision.revision,
+            title: revision.title,
+            body: revision.body,
+            author: info.author.unwrap_or_default(),
+            created_at: revision.created_at.and_utc().to_rfc3339(),
+        }
+    }
+}
+
+impl From<Page> for PageContent {
+    fn from(page: Page) -> Self {
+        PageContent {
+            slug: page.slug,
+            title: page.title,
+            body: page.body,
+            status: page.status,
+        }
+    }
+}
+
+impl From<PluginEvent> for PluginChange {
+    fn from(event: PluginEvent) -> Self {
+        match event {
+            PluginEvent::Installed => PluginChange::Installed,
+            PluginEvent::Enabled => PluginChange::Enabled,
+            PluginEvent::Disabled => PluginChange::Disabled,
+            PluginEvent::Unloaded => PluginChange::Unloaded,
+            PluginEvent::Emitted { .. } => PluginChange::Emitted,
+        }
+    }
+}
+
+pub struct LucleApi {
+    storage: SharedStorage,
+    config: Arc<LucleConfig>,
+    keys: Arc<JwtKeys>,
+    mailer: Mailer,
+    plugins: Arc<PluginHost>,
+}
+
+impl LucleApi {
+    pub fn new(
+        storage: SharedStorage,
+        config: Arc<LucleConfig>,
+        keys: Arc<JwtKeys>,
+        mailer: Mailer,
+        plugins: Arc<PluginHost>,
+    ) -> Self {
+        Self {
+            storage,
+            config,
+            keys,
+            mailer,
+            plugins,
+        }
+    }
+
+    fn storage(&self) -> Result<Arc<dyn Storage>, crate::errors::Error> {
+        self.storage.get()
+    }
+}
 
 #[tonic::async_trait]
 impl Lucle for LucleApi {
     async fn create_db(&self, request: Request<Database>) -> Result<Response<Empty>, Status> {
-        let inner = request.into_inner();
-        let db_type = inner.db_type;
-        let db_name = inner.clone().db_name.unwrap_or("lucle".to_string());
-        match DatabaseType::try_from(db_type) {
-            Ok(DatabaseType::Sqlite) => {
-                if let Err(err) = diesel::create_database("lucle.db").await {
-                    tracing::error!("Unable to create database : {}", err);
-                    return Err(Status::internal(err.to_string()));
-                }
+        // The install wizard creates the database anonymously, afterwards
+        // only an admin can switch to another one
+        let current = self.storage.current();
+        if let Some(storage) = current {
+            if storage.count_users().await? > 0 {
+                Identity::from_request(&request)?
+                    .require_role(&*storage, &[Role::Admin])
+                    .await?;
             }
-            Ok(DatabaseType::Mysql) => {
-                if let Some(db_connection) = inner.db_connection {
-                    if let Err(err) = diesel::create_database(
-                        &("mysql://".to_owned()
-                            + &db_connection.username
-                            + ":"
-                            + &db_connection.password
-                            + "@"
-                            + &db_connection.hostname
-                            + ":"
-                            + &db_connection.port.to_string()
-                            + "/"
-                            + &db_name),
-                    )
-                    .await
-                    {
-                        tracing::error!("Unable to create database : {}", err);
-                        return Err(Status::internal(err.to_string()));
-                    }
-                }
+        }
+        let inner = request.into_inner();
+        let db_name = inner.db_name.unwrap_or("lucle".to_string());
+        if !crate::speedupdate::is_valid_name(&db_name) {
+            return Err(crate::errors::Error::InvalidDatabaseName(db_name).into());
+        }
+        // File databases are created next to the configuration file
+        let (database, file) = match DatabaseType::try_from(inner.db_type) {
+            Ok(DatabaseType::Mysql) => ("mysql", None),
+            Ok(DatabaseType::Postgresql) => ("postgresql", None),
+            Ok(DatabaseType::Sqlite) => ("sqlite", Some(format!("{}.db", db_name))),
+            Ok(DatabaseType::Surrealdb) => ("surrealdb", Some(db_name.clone())),
+            Err(err) => return Err(Status::invalid_argument(err.to_string())),
+        };
+        let url = match file {
+            Some(file) => {
+                let path = std::path::absolute(self.config.dir().join(file))
+                    .map_err(crate::errors::Error::from)?;
+                Some(path.to_string_lossy().into_owned())
             }
-            Ok(DatabaseType::Postgresql) => {
-                if let Err(err) = diesel::create_database("postgres://").await {
+            None => None,
+        };
+        let mut db_config = DatabaseConfig {
+            database: database.to_string(),
+            url,
+            name: Some(db_name),
+            ..Default::default()
+        };
+        if let Some(db_connection) = inner.db_connection {
+            db_config.hostname = Some(db_connection.hostname);
+            db_config.port = Some(db_connection.port);
+            db_config.username = Some(db_connection.username);
+            db_config.password = Some(db_connection.password);
+        }
+
+        let db_type = db_config.db_type()?;
+        match &db_type {
+            DbType::Mysql(url) | DbType::Postgresql(url) | DbType::Sqlite(url) => {
+                if let Err(err) = diesel::create_database(url).await {
                     tracing::error!("Unable to create database : {}", err);
                     return Err(Status::internal(err.to_string()));
                 }
             }
-            Ok(DatabaseType::Surrealdb) => {
-                if let Err(err) = surrealdb::create_database().await {
-                    tracing::error!("Unable to create database : {}", err);
-                    return Err(Status::internal(err.to_string()));
-                }
+            // SurrealKV creates the database and its schema when it is opened
+            DbType::Surrealdb(_) | DbType::NoDatabase => {}
+        }
+
+        let storage = match storage::connect(db_type).await {
+            Ok(storage) => storage,
+            Err(err) => {
+                tracing::error!("Unable to create database : {}", err);
+                return Err(Status::internal(err.to_string()));
             }
-            _ => {}
+        };
+        // Only a database that connected and migrated keeps its password
+        if let Err(err) = db_config.store_password(self.config.dir()) {
+            tracing::error!("Unable to store database password : {}", err);
+            return Err(Status::internal(err.to_string()));
+        }
+        if let Err(err) = db_config.save(&self.config.path) {
+            tracing::error!("Unable to save database configuration : {}", err);
+            return Err(Status::internal(err.to_string()));
         }
+        self.storage.replace(storage);
+        tracing::info!("Now using {} database", db_config.database);
 
         let reply = Empty {};
         Ok(Response::new(reply))
     }
 
     async fn create_user(&self, request: Request<UserCreation>) -> Result<Response<Empty>, Status> {
+        let storage = self.storage()?;
+        // The first user is created by the install wizard and administrates the
+        // instance, the insert checks again that no user was created meanwhile
+        let first = storage.count_users().await? == 0;
+        let role = if first {
+            Role::Admin
+        } else {
+            Identity::from_request(&request)?
+                .require_role(&*storage, &[Role::Admin])
+                .await?;
+            request.get_ref().role.parse()?
+        };
         let inner = request.into_inner();
         let username = inner.username;
         let password = inner.password;
         let email = inner.email;
+        let locale = if inner.locale.is_empty() {
+            self.config.mail.default_locale.clone()
+        } else {
+            inner.locale
+        };
         let reply = Empty {};
         if EmailAddress::is_valid(&email.clone(), None) {
-            match user::create_user(username.clone(), password, email).await {
+            let created = if first {
+                user::create_first_user(&*storage, username.clone(), password, email, locale).await
+            } else {
+                user::create_user(&*storage, username.clone(), password, email, role, locale).await
+            };
+            match created {
                 Ok(()) => {
                     tracing::info!("user {} created", username);
+                    if let Err(err) =
+                        user::send_welcome(&*storage, &self.config, &self.mailer, &username).await
+                    {
+                        tracing::warn!("Unable to send the welcome email to {}: {}", username, err);
+                    }
                     return Ok(Response::new(reply));
                 }
+                Err(err @ crate::errors::Error::PermissionDenied) => {
+                    tracing::error!("{}", err);
+                    return Err(err.into());
+                }
                 Err(err) => {
                     tracing::error!("{}", err);
                     return Err(Status::internal(err.to_string()));
@@ -131,18 +313,21 @@ impl Lucle for LucleApi {
         &self,
         request: Request<UpdateServer>,
     ) -> Result<Response<Empty>, Status> {
-        let inner = request.into_inner();
-        let username = inner.username;
-        let path = inner.path;
+        let storage = self.storage()?;
+        let username = Identity::from_request(&request)?
+            .require_role(&*storage, &[Role::Admin, Role::Maintainer])
+            .await?
+            .username;
+        let path = request.into_inner().path;
         let reply = Empty {};
-        match user::register_update_server(username.clone(), path.clone()).await {
+        match user::register_update_server(&*storage, username.clone(), path.clone()).await {
             Ok(()) => {
                 tracing::info!("User {} created {} repository", username, path);
                 return Ok(Response::new(reply));
             }
             Err(err) => {
                 tracing::error!("{}", err);
-                return Err(Status::internal(err.to_string()));
+                return Err(err.into());
             }
         };
     }
@@ -151,18 +336,25 @@ impl Lucle for LucleApi {
         &self,
         request: Request<UpdateServer>,
     ) -> Result<Response<Empty>, Status> {
-        let inner = request.into_inner();
-        let username = inner.username;
-        let path = inner.path;
+        let username = Identity::from_request(&request)?.username;
+        let path = request.into_inner().path;
         let reply = Empty {};
-        match user::join_update_server(username.clone(), path.clone()).await {
+        match user::join_update_server(
+            &*self.storage()?,
+            &self.config,
+            &self.mailer,
+            username.clone(),
+            path.clone(),
+        )
+        .await
+        {
             Ok(()) => {
                 tracing::info!("User {} ask to join {} repository", username, path);
                 return Ok(Response::new(reply));
             }
             Err(err) => {
                 tracing::error!("{}", err);
-                return Err(Status::internal(err.to_string()));
+                return Err(err.into());
             }
         };
     }
@@ -171,9 +363,8 @@ impl Lucle for LucleApi {
         &self,
         request: Request<Username>,
     ) -> Result<Response<ListUpdateServer>, Status> {
-        let inner = request.into_inner();
-        let username = inner.username;
-        match user::list_update_server_by_user(username).await {
+        let username = Identity::from_request(&request)?.username;
+        match user::list_update_server_by_user(&*self.storage()?, username).await {
             Ok(list) => {
                 let reply = ListUpdateServer { repositories: list };
                 Ok(Response::new(reply))
@@ -185,16 +376,258 @@ impl Lucle for LucleApi {
         }
     }
 
+    async fn list_pending_members(
+        &self,
+        request: Request<UpdateServer>,
+    ) -> Result<Response<PendingMembers>, Status> {
+        let caller = Identity::from_request(&request)?.username;
+        let path = request.into_inner().path;
+        match user::list_pending_members(&*self.storage()?, &caller, &path).await {
+            Ok(usernames) => Ok(Response::new(PendingMembers { usernames })),
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn approve_member(&self, request: Request<Member>) -> Result<Response<Empty>, Status> {
+        let caller = Identity::from_request(&request)?.username;
+        let inner = request.into_inner();
+        let permission = inner.permission.parse()?;
+        match user::approve_member(
+            &*self.storage()?,
+            &self.config,
+            &self.mailer,
+            &caller,
+            inner.repository.clone(),
+            &inner.username,
+            permission,
+        )
+        .await
+        {
+            Ok(()) => {
+                tracing::info!(
+                    "{} granted {} access to {} on {}",
+                    caller,
+                    inner.permission,
+                    inner.username,
+                    inner.repository
+                );
+                Ok(Response::new(Empty {}))
+            }
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn reject_member(&self, request: Request<Member>) -> Result<Response<Empty>, Status> {
+        let caller = Identity::from_request(&request)?.username;
+        let inner = request.into_inner();
+        match user::reject_member(
+            &*self.storage()?,
+            &caller,
+            &inner.repository,
+            &inner.username,
+        )
+        .await
+        {
+            Ok(()) => {
+                tracing::info!(
+                    "{} rejected {} from {}",
+                    caller,
+                    inner.username,
+                    inner.repository
+                );
+                Ok(Response::new(Empty {}))
+            }
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn set_member_permission(
+        &self,
+        request: Request<Member>,
+    ) -> Result<Response<Empty>, Status> {
+        let caller = Identity::from_request(&request)?.username;
+        let inner = request.into_inner();
+        let permission = inner.permission.parse()?;
+        match user::set_member_permission(
+            &*self.storage()?,
+            &caller,
+            inner.repository.clone(),
+            &inner.username,
+            permission,
+        )
+        .await
+        {
+            Ok(()) => {
+                tracing::info!(
+                    "{} changed the access of {} to {} on {}",
+                    caller,
+                    inner.username,
+                    inner.permission,
+                    inner.repository
+                );
+                Ok(Response::new(Empty {}))
+            }
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn remove_member(&self, request: Request<Member>) -> Result<Response<Empty>, Status> {
+        let caller = Identity::from_request(&request)?.username;
+        let inner = request.into_inner();
+        match user::remove_member(
+            &*self.storage()?,
+            &caller,
+            &inner.repository,
+            &inner.username,
+        )
+        .await
+        {
+            Ok(()) => {
+                tracing::info!(
+                    "{} removed {} from {}",
+                    caller,
+                    inner.username,
+                    inner.repository
+                );
+                Ok(Response::new(Empty {}))
+            }
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn get_repository(
+        &self,
+        request: Request<UpdateServer>,
+    ) -> Result<Response<RepositoryInfo>, Status> {
+        let caller = Identity::from_request(&request)?.username;
+        let path = request.into_inner().path;
+        match user::get_repository(&*self.storage()?, &caller, &path).await {
+            Ok(info) => {
+                let members = info
+                    .members
+                    .into_iter()
+                    .map(|(username, permission)| Member {
+                        repository: info.name.clone(),
+                        username,
+                        permission: permission.as_str().to_string(),
+                    })
+                    .collect();
+                let reply = RepositoryInfo {
+                    name: info.name,
+                    owner: info.owner.unwrap_or_default(),
+                    created_at: info.created_at.and_utc().to_rfc3339(),
+                    members,
+                };
+                Ok(Response::new(reply))
+            }
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn delete_repository(
+        &self,
+        request: Request<UpdateServer>,
+    ) -> Result<Response<Empty>, Status> {
+        let caller = Identity::from_request(&request)?.username;
+        let path = request.into_inner().path;
+        match user::delete_repository(&*self.storage()?, &self.config, &caller, &path).await {
+            Ok(()) => {
+                tracing::info!("{} deleted {} repository", caller, path);
+                Ok(Response::new(Empty {}))
+            }
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn rename_repository(
+        &self,
+        request: Request<RenameRepository>,
+    ) -> Result<Response<Empty>, Status> {
+        let caller = Identity::from_request(&request)?.username;
+        let inner = request.into_inner();
+        match user::rename_repository(
+            &*self.storage()?,
+            &self.config,
+            &caller,
+            &inner.path,
+            inner.new_path.clone(),
+        )
+        .await
+        {
+            Ok(()) => {
+                tracing::info!(
+                    "{} renamed {} repository to {}",
+                    caller,
+                    inner.path,
+                    inner.new_path
+                );
+                Ok(Response::new(Empty {}))
+            }
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn transfer_repository(
+        &self,
+        request: Request<TransferRepository>,
+    ) -> Result<Response<Empty>, Status> {
+        let caller = Identity::from_request(&request)?.username;
+        let inner = request.into_inner();
+        match user::transfer_repository(&*self.storage()?, &caller, &inner.path, &inner.username)
+            .await
+        {
+            Ok(()) => {
+                tracing::info!(
+                    "{} transferred {} repository to {}",
+                    caller,
+                    inner.path,
+                    inner.username
+                );
+                Ok(Response::new(Empty {}))
+            }
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
     async fn login(&self, request: Request<Credentials>) -> Result<Response<User>, Status> {
         let inner = request.into_inner();
         let username_or_email = inner.username_or_email;
         let password = inner.password;
-        match user::login(username_or_email, password).await {
+        match user::login(&*self.storage()?, &self.keys, username_or_email, password).await {
             Ok(user) => {
                 let user = User {
                     username: user.username,
-                    token: user.token,
+                    role: user.role.as_str().to_string(),
+                    token: user.tokens.token,
                     repositories: user.repositories,
+                    refresh_token: user.tokens.refresh_token,
                 };
                 Ok(Response::new(user))
             }
@@ -205,12 +638,40 @@ impl Lucle for LucleApi {
         }
     }
 
+    async fn refresh(&self, request: Request<RefreshToken>) -> Result<Response<Tokens>, Status> {
+        let inner = request.into_inner();
+        match user::refresh(&*self.storage()?, &self.keys, &inner.refresh_token).await {
+            Ok(tokens) => {
+                let reply = Tokens {
+                    token: tokens.token,
+                    refresh_token: tokens.refresh_token,
+                };
+                Ok(Response::new(reply))
+            }
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn revoke(&self, request: Request<RefreshToken>) -> Result<Response<Empty>, Status> {
+        let inner = request.into_inner();
+        match user::revoke(&*self.storage()?, &self.keys, &inner.refresh_token).await {
+            Ok(()) => Ok(Response::new(Empty {})),
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
     async fn is_database_created(
         &self,
         _request: Request<Empty>,
     ) -> Result<Response<Empty>, Status> {
         let reply = Empty {};
-        match user::is_table_and_user_created().await {
+        match user::is_table_and_user_created(&*self.storage()?).await {
             Ok(()) => Ok(Response::new(reply)),
             Err(err) => {
                 tracing::error!("{}", err);
@@ -227,14 +688,142 @@ impl Lucle for LucleApi {
         let email = inner.email;
         let reply = Empty {};
         if EmailAddress::is_valid(email.as_str(), None) {
-            if let Err(err) = user::reset_password(email).await {
+            if let Err(err) = user::reset_password(
+                &*self.storage()?,
+                &self.config,
+                &self.keys,
+                &self.mailer,
+                email,
+            )
+            .await
+            {
+                // The reply is the same whether or not the address has an
+                // account and the email could be sent
                 tracing::error!("{}", err);
-                return Err(Status::internal(err.to_string()));
             }
         }
         Ok(Response::new(reply))
     }
 
+    async fn confirm_password_reset(
+        &self,
+        request: Request<ConfirmPasswordReset>,
+    ) -> Result<Response<Empty>, Status> {
+        let inner = request.into_inner();
+        match user::confirm_password_reset(
+            &*self.storage()?,
+            &self.keys,
+            &inner.token,
+            &inner.new_password,
+        )
+        .await
+        {
+            Ok(()) => Ok(Response::new(Empty {})),
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn list_plugins(&self, request: Request<Empty>) -> Result<Response<Plugins>, Status> {
+        Identity::from_request(&request)?
+            .require_role(&*self.storage()?, &[Role::Admin])
+            .await?;
+        let plugins = self.plugins.list().into_iter().map(Plugin::from).collect();
+        Ok(Response::new(Plugins { plugins }))
+    }
+
+    async fn install_plugin(
+        &self,
+        request: Request<Streaming<PluginArchive>>,
+    ) -> Result<Response<Plugin>, Status> {
+        let storage = self.storage()?;
+        let caller = Identity::from_request(&request)?;
+        caller.require_role(&*storage, &[Role::Admin]).await?;
+        let max_size = self.config.plugins.max_size;
+        let mut chunks = request.into_inner();
+        let mut archive = Vec::new();
+        while let Some(chunk) = chunks.message().await? {
+            if (archive.len() + chunk.data.len()) as u64 > max_size {
+                let err =
+                    crate::errors::Error::UploadTooLarge("plugin archive".to_string(), max_size);
+                tracing::error!("{}", err);
+                return Err(err.into());
+            }
+            archive.extend_from_slice(&chunk.data);
+        }
+        match self
+            .plugins
+            .install(&self.config.plugins, &*storage, archive)
+            .await
+        {
+            Ok(plugin) => {
+                tracing::info!("{} installed {} plugin", caller.username, plugin.name);
+                Ok(Response::new(plugin.into()))
+            }
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn enable_plugin(
+        &self,
+        request: Request<PluginName>,
+    ) -> Result<Response<Plugin>, Status> {
+        let storage = self.storage()?;
+        Identity::from_request(&request)?
+            .require_role(&*storage, &[Role::Admin])
+            .await?;
+        let name = request.into_inner().name;
+        match self
+            .plugins
+            .enable(&self.config.plugins, &*storage, &name)
+            .await
+        {
+            Ok(plugin) => Ok(Response::new(plugin.into())),
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn disable_plugin(
+        &self,
+        request: Request<PluginName>,
+    ) -> Result<Response<Plugin>, Status> {
+        let storage = self.storage()?;
+        Identity::from_request(&request)?
+            .require_role(&*storage, &[Role::Admin])
+            .await?;
+        let name = request.into_inner().name;
+        match self.plugins.disable(&*storage, &name).await {
+            Ok(plugin) => Ok(Response::new(plugin.into())),
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn unload_plugin(&self, request: Request<PluginName>) -> Result<Response<Empty>, Status> {
+        let storage = self.storage()?;
+        Identity::from_request(&request)?
+            .require_role(&*storage, &[Role::Admin])
+            .await?;
+        let name = request.into_inner().name;
+        match self.plugins.unload(&*storage, &name).await {
+            Ok(()) => Ok(Response::new(Empty {})),
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
     type ServerStreamingEchoStream = ResponseStream;
 
     async fn server_streaming_echo(
@@ -243,32 +832,49 @@ impl Lucle for LucleApi {
     ) -> StreamResult<Self::ServerStreamingEchoStream> {
         tracing::info!("client connected from {:?}", req.remote_addr().unwrap());
 
-        let message = Message {
-            plugin: "allo".to_string(),
-        };
-
         let mut in_stream = req.into_inner();
+        let mut events = self.plugins.subscribe();
         let (tx, rx) = mpsc::channel(128);
 
+        // Clients keep the stream open while they wait for changes of the
+        // plugin set
         tokio::spawn(async move {
-            while let Some(result) = in_stream.next().await {
-                match result {
-                    Ok(_) => tx
-                        .send(Result::<_, Status>::Ok(message.clone()))
-                        .await
-                        .expect("working rx"),
-                    Err(err) => {
-                        if let Some(io_err) = match_for_io_error(&err) {
-                            if io_err.kind() == ErrorKind::BrokenPipe {
-                                tracing::error!("client disconnected: broken pipe");
+            loop {
+                tokio::select! {
+                    result = in_stream.next() => match result {
+                        Some(Ok(_)) => {}
+                        Some(Err(err)) => {
+                            if let Some(io_err) = match_for_io_error(&err) {
+                                if io_err.kind() == ErrorKind::BrokenPipe {
+                                    tracing::error!("client disconnected: broken pipe");
+                                    break;
+                                }
+                            }
+                            if tx.send(Err(err)).await.is_err() {
                                 break;
                             }
                         }
-                        match tx.send(Err(err)).await {
-                            Ok(_) => (),
-                            Err(_err) => break,
+                        None => break,
+                    },
+                    event = events.recv() => match event {
+                        Ok((plugin, event)) => {
+                            let mut message = Message {
+                                plugin,
+                                change: PluginChange::from(event.clone()).into(),
+                                ..Default::default()
+                            };
+                            if let PluginEvent::Emitted { event, data } = event {
+                                message.event = event;
+                                message.data = data;
+                            }
+                            if tx.send(Ok(message)).await.is_err() {
+                                break;
+                            }
                         }
-                    }
+                        // Dropped changes are not replayed, clients list the plugins again
+                        Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => break,
+                    },
                 }
             }
             tracing::info!("stream ended");
@@ -279,16 +885,174 @@ impl Lucle for LucleApi {
             Box::pin(output_stream) as Self::ServerStreamingEchoStream
         ))
     }
+
+    async fn list_pages(&self, request: Request<Empty>) -> Result<Response<Pages>, Status> {
+        let caller = Identity::from_request(&request)?.username;
+        match page::list_pages(&*self.storage()?, &caller).await {
+            Ok(pages) => {
+                let pages = pages.into_iter().map(Page::from).collect();
+                Ok(Response::new(Pages { pages }))
+            }
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn get_page(&self, request: Request<PageSlug>) -> Result<Response<Page>, Status> {
+        let caller = Identity::from_request(&request)?.username;
+        let slug = request.into_inner().slug;
+        match page::get_page(&*self.storage()?, &caller, &slug).await {
+            Ok(page) => Ok(Response::new(page.into())),
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn create_page(&self, request: Request<Page>) -> Result<Response<Page>, Status> {
+        let caller = Identity::from_request(&request)?.username;
+        let content = request.into_inner().into();
+        match page::create_page(&*self.storage()?, &caller, content).await {
+            Ok(page) => {
+                tracing::info!("{} created {} page", caller, page.page.slug);
+                Ok(Response::new(page.into()))
+            }
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn update_page(
+        &self,
+        request: Request<PageUpdate>,
+    ) -> Result<Response<PageUpdateResult>, Status> {
+        let caller = Identity::from_request(&request)?.username;
+        let inner = request.into_inner();
+        let content = inner.page.unwrap_or_default().into();
+        match page::update_page(
+            &*self.storage()?,
+            &caller,
+            &inner.slug,
+            content,
+            inner.base_revision,
+        )
+        .await
+        {
+            Ok(save) => {
+                match &save {
+                    PageSave::Saved(_) => tracing::info!("{} updated {} page", caller, inner.slug),
+                    PageSave::Conflict { .. } => {
+                        tracing::info!("{} edits of {} page conflict", caller, inner.slug)
+                    }
+                }
+                Ok(Response::new(save.into()))
+            }
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn delete_page(&self, request: Request<PageSlug>) -> Result<Response<Empty>, Status> {
+        let caller = Identity::from_request(&request)?.username;
+        let slug = request.into_inner().slug;
+        match page::delete_page(&*self.storage()?, &caller, &slug).await {
+            Ok(()) => {
+                tracing::info!("{} deleted {} page", caller, slug);
+                Ok(Response::new(Empty {}))
+            }
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn list_page_revisions(
+        &self,
+        request: Request<PageSlug>,
+    ) -> Result<Response<PageRevisions>, Status> {
+        let caller = Identity::from_request(&request)?.username;
+        let slug = request.into_inner().slug;
+        match page::list_revisions(&*self.storage()?, &caller, &slug).await {
+            Ok(revisions) => {
+                let revisions = revisions.into_iter().map(PageRevision::from).collect();
+                Ok(Response::new(PageRevisions { revisions }))
+            }
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn diff_page_revisions(
+        &self,
+        request: Request<PageRevisionDiff>,
+    ) -> Result<Response<PagePatch>, Status> {
+        let caller = Identity::from_request(&request)?.username;
+        let inner = request.into_inner();
+        match page::diff_revisions(
+            &*self.storage()?,
+            &caller,
+            &inner.slug,
+            inner.from_revision,
+            inner.to_revision,
+        )
+        .await
+        {
+            Ok(patch) => Ok(Response::new(PagePatch { patch })),
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn rollback_page(
+        &self,
+        request: Request<PageRollback>,
+    ) -> Result<Response<Page>, Status> {
+        let caller = Identity::from_request(&request)?.username;
+        let inner = request.into_inner();
+        match page::rollback_page(&*self.storage()?, &caller, &inner.slug, inner.revision).await {
+            Ok(page) => {
+                tracing::info!(
+                    "{} rolled {} page back to revision {}",
+                    caller,
+                    inner.slug,
+                    inner.revision
+                );
+                Ok(Response::new(page.into()))
+            }
+            Err(err) => {
+                tracing::error!("{}", err);
+                Err(err.into())
+            }
+        }
+    }
 }
 
 pub async fn rpc_api(
     _cert: &mut BufReader<File>,
     _key: &mut BufReader<File>,
-    _db: DbType,
+    storage: SharedStorage,
+    config: Arc<LucleConfig>,
+    plugins: Arc<PluginHost>,
+    shutdown: watch::Receiver<bool>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let addr = "127.0.0.1:3000".parse().unwrap();
+    let addr = config.listeners.grpc;
 
-    let api = LucleApi::default();
+    let keys = Arc::new(JwtKeys::load(&config.jwt)?);
+    let mailer = Mailer::new(&config.mail)?;
+    let repo = RepoServer::new(RepoApi::new(storage.clone(), config.clone()));
+    let api = LucleApi::new(storage, config, keys.clone(), mailer, plugins.clone());
     let api = LucleServer::new(api);
 
     let cors_layer = CorsLayer::new()
@@ -298,13 +1062,20 @@ pub async fn rpc_api(
 
     let mut routes_builder = RoutesBuilder::default();
     routes_builder.add_service(api);
+    routes_builder.add_service(repo);
+    // Methods of no lucle service are dispatched to the plugins
+    let routes = routes_builder
+        .routes()
+        .into_axum_router()
+        .fallback(move |request| plugin::grpc_fallback(plugins.clone(), request));
 
     Server::builder()
         .accept_http1(true)
         .layer(cors_layer)
         .layer(GrpcWebLayer::new())
-        .add_routes(routes_builder.routes())
-        .serve(addr)
+        .layer(AuthLayer::new(keys))
+        .add_routes(routes.into())
+        .serve_with_shutdown(addr, crate::utils::shutdown_requested(shutdown))
         .await?;
 
     Ok(())
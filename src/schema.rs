@@ -3,7 +3,74 @@
 pub mod sql_types {
     #[derive(diesel::sql_types::SqlType)]
     #[diesel(mysql_type(name = "Enum"))]
+    #[diesel(postgres_type(name = "page_status"))]
+    #[diesel(sqlite_type(name = "Text"))]
+    pub struct PagesStatusEnum;
+
+    #[derive(diesel::sql_types::SqlType)]
+    #[diesel(mysql_type(name = "Enum"))]
+    #[diesel(postgres_type(name = "permission"))]
+    #[diesel(sqlite_type(name = "Text"))]
     pub struct UsersRepositoriesPermissionEnum;
+
+    #[derive(diesel::sql_types::SqlType)]
+    #[diesel(mysql_type(name = "Enum"))]
+    #[diesel(postgres_type(name = "user_role"))]
+    #[diesel(sqlite_type(name = "Text"))]
+    pub struct UsersRoleEnum;
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::PagesStatusEnum;
+
+    pages (id) {
+        id -> Integer,
+        #[max_length = 255]
+        slug -> Varchar,
+        #[max_length = 255]
+        title -> Varchar,
+        body -> Text,
+        author_id -> Integer,
+        #[max_length = 9]
+        status -> PagesStatusEnum,
+        created_at -> Timestamp,
+        modified_at -> Timestamp,
+        published_at -> Nullable<Timestamp>,
+        revision -> Integer,
+    }
+}
+
+diesel::table! {
+    page_revisions (page_slug, revision) {
+        #[max_length = 255]
+        page_slug -> Varchar,
+        revision -> Integer,
+        #[max_length = 255]
+        title -> Varchar,
+        body -> Text,
+        author_id -> Integer,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    plugins (name) {
+        #[max_length = 255]
+        name -> Varchar,
+        enabled -> Bool,
+        installed_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    refresh_tokens (id) {
+        #[max_length = 32]
+        id -> Varchar,
+        user_id -> Integer,
+        expires_at -> Timestamp,
+        revoked -> Bool,
+    }
 }
 
 diesel::table! {
@@ -12,10 +79,14 @@ diesel::table! {
         #[max_length = 255]
         name -> Varchar,
         created_at -> Timestamp,
+        owner_id -> Nullable<Integer>,
     }
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::UsersRoleEnum;
+
     users (id) {
         id -> Integer,
         username -> Text,
@@ -24,6 +95,10 @@ diesel::table! {
         created_at -> Timestamp,
         modified_at -> Timestamp,
         reset_token -> Nullable<Text>,
+        #[max_length = 10]
+        role -> UsersRoleEnum,
+        #[max_length = 16]
+        locale -> Varchar,
     }
 }
 
@@ -35,9 +110,17 @@ diesel::table! {
         user_id -> Integer,
         #[max_length = 255]
         repository_name -> Varchar,
-        #[max_length = 5]
+        #[max_length = 7]
         permission -> UsersRepositoriesPermissionEnum,
     }
 }
 
-diesel::allow_tables_to_appear_in_same_query!(repositories, users, users_repositories,);
+diesel::allow_tables_to_appear_in_same_query!(
+    page_revisions,
+    pages,
+    plugins,
+    refresh_tokens,
+    repositories,
+    users,
+    users_repositories,
+);
@@ -0,0 +1,112 @@
+use crate::errors::Error;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use minisign_verify::{PublicKey, Signature};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::{fs, io::AsyncReadExt};
+
+pub const SIGNATURE_EXTENSION: &str = "minisig";
+
+/// Minisign public key allowed to sign artifacts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrustedKey {
+    /// As printed by `minisign -G`, 16 uppercase hexadecimal digits
+    pub id: String,
+    /// Base64 line of the `.pub` file
+    pub public_key: String,
+}
+
+impl TrustedKey {
+    /// Parse the base64 line of a minisign public key, or a whole `.pub`
+    /// file
+    pub fn parse(public_key: &str) -> Result<Self, Error> {
+        let invalid = || Error::InvalidPublicKey(public_key.trim().to_string());
+        let line = public_key
+            .lines()
+            .map(str::trim)
+            .rfind(|line| !line.is_empty() && !line.starts_with("untrusted comment:"))
+            .ok_or_else(invalid)?;
+        PublicKey::from_base64(line).map_err(|_| invalid())?;
+        // Algorithm, then the key id stored as a little endian integer
+        let bytes = STANDARD.decode(line).map_err(|_| invalid())?;
+        let id = bytes[2..10]
+            .iter()
+            .rev()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        Ok(Self {
+            id,
+            public_key: line.to_string(),
+        })
+    }
+}
+
+/// Path of the detached signature of `file`
+pub fn signature_path(file: &Path) -> PathBuf {
+    let mut path = file.as_os_str().to_owned();
+    path.push(".");
+    path.push(SIGNATURE_EXTENSION);
+    PathBuf::from(path)
+}
+
+/// Check `file` against its detached `.minisig` signature, made by one of
+/// `trusted_keys`. The file is read in chunks, `progress` is called with the
+/// bytes read so far. Returns the id of the signing key and the size of the
+/// file.
+pub async fn verify_file(
+    file: &Path,
+    trusted_keys: &[TrustedKey],
+    mut progress: impl FnMut(u64) + Send,
+) -> Result<(String, u64), Error> {
+    let name = file
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let signature = match fs::read_to_string(signature_path(file)).await {
+        Ok(content) => Signature::decode(&content)
+            .map_err(|err| Error::SignatureInvalid(name.clone(), err.to_string()))?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::SignatureMissing(name))
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    // Only the key with the id of the signature accepts it
+    let mut signer = None;
+    for trusted in trusted_keys {
+        let Ok(key) = PublicKey::from_base64(&trusted.public_key) else {
+            continue;
+        };
+        match key.verify_stream(&signature) {
+            Ok(_) => {
+                signer = Some((trusted, key));
+                break;
+            }
+            Err(minisign_verify::Error::UnexpectedKeyId) => continue,
+            Err(err) => return Err(Error::SignatureInvalid(name, err.to_string())),
+        }
+    }
+    let Some((trusted, key)) = signer else {
+        return Err(Error::UntrustedSignature(name));
+    };
+    let mut verifier = key
+        .verify_stream(&signature)
+        .map_err(|err| Error::SignatureInvalid(name.clone(), err.to_string()))?;
+
+    let mut data = fs::File::open(file).await?;
+    let mut buffer = vec![0; 64 * 1024];
+    let mut done = 0;
+    loop {
+        let read = data.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        verifier.update(&buffer[..read]);
+        done += read as u64;
+        progress(done);
+    }
+    verifier
+        .finalize()
+        .map_err(|err| Error::SignatureInvalid(name, err.to_string()))?;
+    Ok((trusted.id.clone(), done))
+}
@@ -0,0 +1,881 @@
+use crate::errors::Error;
+use crate::signature::{self, TrustedKey};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+
+const CURRENT_FILE: &str = "current";
+const VERSIONS_FILE: &str = "versions";
+const PACKAGES_FILE: &str = "packages";
+/// Minisign public keys allowed to sign packages
+const KEYS_FILE: &str = "keys";
+const METADATA_EXTENSION: &str = "metadata";
+/// Bytes read between two progress reports while verifying a package
+const PROGRESS_STEP: u64 = 1 << 20;
+/// Uploaded binaries waiting to be packaged
+pub const BINARIES_DIR: &str = "binaries";
+/// Uploads in progress, `<file>.part` holds the bytes received and
+/// `<file>.upload` the expected size and checksum
+const STAGING_DIR: &str = ".staging";
+/// Files of the repository a package cannot be named after
+const RESERVED_NAMES: &[&str] = &[
+    CURRENT_FILE,
+    VERSIONS_FILE,
+    PACKAGES_FILE,
+    KEYS_FILE,
+    BINARIES_DIR,
+];
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Current {
+    version: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Version {
+    pub revision: String,
+    #[serde(default)]
+    pub description: String,
+    /// Key id of the signature of the last package registered to this version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signer: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Versions {
+    versions: Vec<Version>,
+}
+
+/// Written by the packager next to the package data as `<name>.metadata`.
+/// A package without `from` installs `to` from scratch, otherwise it patches
+/// `from` into `to`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackageMetadata {
+    pub from: Option<String>,
+    pub to: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Package {
+    name: String,
+    #[serde(flatten)]
+    metadata: PackageMetadata,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signer: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Packages {
+    packages: Vec<Package>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Keys {
+    keys: Vec<TrustedKey>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct UploadInfo {
+    size: u64,
+    sha256: String,
+}
+
+pub struct RepositoryStatus {
+    pub current_version: Option<String>,
+    pub versions: Vec<Version>,
+    /// Registered packages, the ones clients download
+    pub packages: Vec<String>,
+    /// Packages on disk that are not registered yet
+    pub available_packages: Vec<String>,
+    pub available_binaries: Vec<String>,
+    /// Bytes used by the repository on disk
+    pub size: u64,
+}
+
+/// Update repository stored in `<paths.repositories>/<name>`. The `current`,
+/// `versions` and `packages` JSON files index the versions and the packages
+/// published to clients, package data lives next to them.
+pub struct UpdateRepository {
+    name: String,
+    dir: PathBuf,
+}
+
+impl UpdateRepository {
+    pub fn open(root: &Path, name: &str) -> Result<Self, Error> {
+        if !is_valid_name(name) {
+            return Err(Error::InvalidRepositoryName(name.to_string()));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            dir: root.join(name),
+        })
+    }
+
+    pub async fn is_init(&self) -> bool {
+        for file in [CURRENT_FILE, VERSIONS_FILE, PACKAGES_FILE] {
+            if !fs::try_exists(self.dir.join(file)).await.unwrap_or(false) {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub async fn init(&self) -> Result<(), Error> {
+        if self.is_init().await {
+            return Err(Error::RepositoryAlreadyInitialized(self.name.clone()));
+        }
+        fs::create_dir_all(self.dir.join(BINARIES_DIR)).await?;
+        self.write(CURRENT_FILE, &Current::default()).await?;
+        self.write(VERSIONS_FILE, &Versions::default()).await?;
+        self.write(PACKAGES_FILE, &Packages::default()).await
+    }
+
+    pub async fn status(&self) -> Result<RepositoryStatus, Error> {
+        self.check_init().await?;
+        let current: Current = self.read(CURRENT_FILE).await?;
+        let versions: Versions = self.read(VERSIONS_FILE).await?;
+        let packages: Packages = self.read(PACKAGES_FILE).await?;
+
+        let mut available_packages = Vec::new();
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(METADATA_EXTENSION) {
+                continue;
+            }
+            if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                if !packages.packages.iter().any(|package| package.name == name) {
+                    available_packages.push(name.to_string());
+                }
+            }
+        }
+        available_packages.sort();
+
+        let mut available_binaries = Vec::new();
+        if let Ok(mut entries) = fs::read_dir(self.dir.join(BINARIES_DIR)).await {
+            while let Some(entry) = entries.next_entry().await? {
+                if entry.file_type().await?.is_file() {
+                    available_binaries.push(entry.file_name().to_string_lossy().into_owned());
+                }
+            }
+        }
+        available_binaries.sort();
+
+        Ok(RepositoryStatus {
+            current_version: current.version,
+            versions: versions.versions,
+            packages: packages
+                .packages
+                .into_iter()
+                .map(|package| package.name)
+                .collect(),
+            available_packages,
+            available_binaries,
+            size: dir_size(&self.dir).await?,
+        })
+    }
+
+    pub async fn set_current_version(&self, revision: &str) -> Result<(), Error> {
+        self.check_init().await?;
+        let versions: Versions = self.read(VERSIONS_FILE).await?;
+        if !versions
+            .versions
+            .iter()
+            .any(|version| version.revision == revision)
+        {
+            return Err(Error::VersionNotFound(revision.to_string()));
+        }
+        let current = Current {
+            version: Some(revision.to_string()),
+        };
+        self.write(CURRENT_FILE, &current).await
+    }
+
+    pub async fn register_version(&self, revision: &str, description: &str) -> Result<(), Error> {
+        self.check_init().await?;
+        if revision.is_empty() {
+            return Err(Error::VersionEmpty);
+        }
+        let mut versions: Versions = self.read(VERSIONS_FILE).await?;
+        if versions
+            .versions
+            .iter()
+            .any(|version| version.revision == revision)
+        {
+            return Err(Error::VersionExists(revision.to_string()));
+        }
+        versions.versions.push(Version {
+            revision: revision.to_string(),
+            description: description.to_string(),
+            signer: None,
+        });
+        self.write(VERSIONS_FILE, &versions).await
+    }
+
+    /// The current version and versions registered packages lead to stay
+    pub async fn unregister_version(&self, revision: &str) -> Result<(), Error> {
+        self.check_init().await?;
+        let current: Current = self.read(CURRENT_FILE).await?;
+        let packages: Packages = self.read(PACKAGES_FILE).await?;
+        if current.version.as_deref() == Some(revision)
+            || packages
+                .packages
+                .iter()
+                .any(|package| package.metadata.to == revision)
+        {
+            return Err(Error::VersionInUse(revision.to_string()));
+        }
+        let mut versions: Versions = self.read(VERSIONS_FILE).await?;
+        let count = versions.versions.len();
+        versions
+            .versions
+            .retain(|version| version.revision != revision);
+        if versions.versions.len() == count {
+            return Err(Error::VersionNotFound(revision.to_string()));
+        }
+        self.write(VERSIONS_FILE, &versions).await
+    }
+
+    /// Publish a package, the version it leads to must be registered. The
+    /// package is `<name>`, `<name>.metadata` and `<name>.minisig`, either
+    /// found next to the index files or uploaded as binaries, in which case
+    /// they are moved next to the index once verified. Its data is read back
+    /// to check it matches the metadata and its signature is made by a
+    /// trusted key, whose id is recorded with the package and the version.
+    /// `progress` is called with the bytes read so far and the total.
+    pub async fn register_package(
+        &self,
+        name: &str,
+        mut progress: impl FnMut(u64, u64) + Send,
+    ) -> Result<(), Error> {
+        self.check_init().await?;
+        if !is_valid_name(name) || RESERVED_NAMES.contains(&name) {
+            return Err(Error::InvalidFileName(name.to_string()));
+        }
+        let metadata_file = format!("{}.{}", name, METADATA_EXTENSION);
+        let source = if fs::try_exists(self.dir.join(&metadata_file)).await? {
+            self.dir.clone()
+        } else {
+            self.dir.join(BINARIES_DIR)
+        };
+        let metadata = package_metadata(&source, name).await?;
+        let versions: Versions = self.read(VERSIONS_FILE).await?;
+        if !versions
+            .versions
+            .iter()
+            .any(|version| version.revision == metadata.to)
+        {
+            return Err(Error::VersionNotFound(metadata.to));
+        }
+        let mut packages: Packages = self.read(PACKAGES_FILE).await?;
+        if packages.packages.iter().any(|package| package.name == name) {
+            return Err(Error::PackageExists(name.to_string()));
+        }
+
+        let data = source.join(name);
+        if !fs::try_exists(&data).await? {
+            return Err(Error::PackageCorrupted(name.to_string()));
+        }
+        let trusted_keys = self.trusted_keys().await?;
+        let total = metadata.size;
+        let mut reported = 0;
+        progress(0, total);
+        let (signer, size) = signature::verify_file(&data, &trusted_keys, |done| {
+            if done - reported >= PROGRESS_STEP {
+                progress(done, total);
+                reported = done;
+            }
+        })
+        .await?;
+        if size != total {
+            return Err(Error::PackageCorrupted(name.to_string()));
+        }
+        if reported != size {
+            progress(size, total);
+        }
+        if source != self.dir {
+            // The metadata goes last, a package without it is not listed
+            let target = self.dir.join(name);
+            fs::rename(
+                signature::signature_path(&data),
+                signature::signature_path(&target),
+            )
+            .await?;
+            fs::rename(&data, &target).await?;
+            fs::rename(source.join(&metadata_file), self.dir.join(&metadata_file)).await?;
+        }
+
+        let mut versions = versions;
+        if let Some(version) = versions
+            .versions
+            .iter_mut()
+            .find(|version| version.revision == metadata.to)
+        {
+            version.signer = Some(signer.clone());
+        }
+        packages.packages.push(Package {
+            name: name.to_string(),
+            metadata,
+            signer: Some(signer),
+        });
+        self.write(PACKAGES_FILE, &packages).await?;
+        self.write(VERSIONS_FILE, &versions).await
+    }
+
+    pub async fn unregister_package(&self, name: &str) -> Result<(), Error> {
+        self.check_init().await?;
+        let mut packages: Packages = self.read(PACKAGES_FILE).await?;
+        let count = packages.packages.len();
+        packages.packages.retain(|package| package.name != name);
+        if packages.packages.len() == count {
+            return Err(Error::PackageNotFound(name.to_string()));
+        }
+        self.write(PACKAGES_FILE, &packages).await
+    }
+
+    pub async fn trusted_keys(&self) -> Result<Vec<TrustedKey>, Error> {
+        self.check_init().await?;
+        match fs::read(self.dir.join(KEYS_FILE)).await {
+            Ok(content) => Ok(serde_json::from_slice::<Keys>(&content)?.keys),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Trust a minisign public key to sign the packages of the repository
+    pub async fn add_trusted_key(&self, public_key: &str) -> Result<TrustedKey, Error> {
+        let key = TrustedKey::parse(public_key)?;
+        let mut keys = self.trusted_keys().await?;
+        if !keys.iter().any(|trusted| trusted.id == key.id) {
+            keys.push(key.clone());
+            self.write(KEYS_FILE, &Keys { keys }).await?;
+        }
+        Ok(key)
+    }
+
+    /// Registered packages stay published, new ones can no longer be signed
+    /// by the key
+    pub async fn remove_trusted_key(&self, id: &str) -> Result<(), Error> {
+        let mut keys = self.trusted_keys().await?;
+        let count = keys.len();
+        keys.retain(|trusted| !trusted.id.eq_ignore_ascii_case(id));
+        if keys.len() == count {
+            return Err(Error::TrustedKeyNotFound(id.to_string()));
+        }
+        self.write(KEYS_FILE, &Keys { keys }).await
+    }
+
+    /// Delete an unregistered package, or an uploaded binary. `progress` is
+    /// called with the files removed so far and the total.
+    pub async fn delete_file(
+        &self,
+        file: &str,
+        mut progress: impl FnMut(u64, u64) + Send,
+    ) -> Result<(), Error> {
+        self.check_init().await?;
+        if !is_valid_name(file) {
+            return Err(Error::InvalidFileName(file.to_string()));
+        }
+        let metadata = self.dir.join(format!("{}.{}", file, METADATA_EXTENSION));
+        let binary = self.dir.join(BINARIES_DIR).join(file);
+        let files = if fs::try_exists(&metadata).await? {
+            let packages: Packages = self.read(PACKAGES_FILE).await?;
+            if packages.packages.iter().any(|package| package.name == file) {
+                return Err(Error::PackageRegistered(file.to_string()));
+            }
+            // The metadata goes last, a package without it is not listed
+            let data = self.dir.join(file);
+            let mut files = Vec::new();
+            for path in [signature::signature_path(&data), data] {
+                if fs::try_exists(&path).await? {
+                    files.push(path);
+                }
+            }
+            files.push(metadata);
+            files
+        } else if fs::try_exists(&binary).await? {
+            vec![binary]
+        } else {
+            return Err(Error::PackageNotFound(file.to_string()));
+        };
+
+        let total = files.len() as u64;
+        progress(0, total);
+        for (done, file) in files.into_iter().enumerate() {
+            fs::remove_file(file).await?;
+            progress(done as u64 + 1, total);
+        }
+        Ok(())
+    }
+
+    /// Bytes of `file` already received, to resume its upload, and whether
+    /// it was completed
+    pub async fn uploaded(&self, file: &str) -> Result<(u64, bool), Error> {
+        self.check_init().await?;
+        if !is_valid_name(file) {
+            return Err(Error::InvalidFileName(file.to_string()));
+        }
+        if fs::try_exists(self.dir.join(BINARIES_DIR).join(file)).await? {
+            return Ok((
+                fs::metadata(self.dir.join(BINARIES_DIR).join(file))
+                    .await?
+                    .len(),
+                true,
+            ));
+        }
+        match fs::metadata(self.staging(file, "part")).await {
+            Ok(metadata) => Ok((metadata.len(), false)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok((0, false)),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Start the upload of a binary, or resume it when `size` and `sha256`
+    /// match the staged one. Other staged data of the file is dropped.
+    pub async fn upload(
+        &self,
+        file: &str,
+        size: u64,
+        sha256: &str,
+        max_size: u64,
+    ) -> Result<Upload, Error> {
+        self.check_init().await?;
+        if !is_valid_name(file) {
+            return Err(Error::InvalidFileName(file.to_string()));
+        }
+        if size > max_size {
+            return Err(Error::UploadTooLarge(file.to_string(), max_size));
+        }
+        if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::InvalidChecksum(sha256.to_string()));
+        }
+        let target = self.dir.join(BINARIES_DIR).join(file);
+        if fs::try_exists(&target).await? {
+            return Err(Error::BinaryExists(file.to_string()));
+        }
+
+        fs::create_dir_all(self.dir.join(STAGING_DIR)).await?;
+        let info = UploadInfo {
+            size,
+            sha256: sha256.to_ascii_lowercase(),
+        };
+        let (info_path, part) = (self.staging(file, "upload"), self.staging(file, "part"));
+        let staged = match fs::read(&info_path).await {
+            Ok(content) => serde_json::from_slice::<UploadInfo>(&content).ok(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+        if staged.as_ref() != Some(&info) {
+            fs::write(&part, b"").await?;
+            fs::write(&info_path, serde_json::to_vec(&info)?).await?;
+        }
+        let handle = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&part)
+            .await?;
+        let received = handle.metadata().await?.len();
+        Ok(Upload {
+            file: file.to_string(),
+            info,
+            info_path,
+            part,
+            target,
+            received,
+            handle,
+        })
+    }
+
+    fn staging(&self, file: &str, extension: &str) -> PathBuf {
+        self.dir
+            .join(STAGING_DIR)
+            .join(format!("{}.{}", file, extension))
+    }
+
+    async fn check_init(&self) -> Result<(), Error> {
+        if self.is_init().await {
+            Ok(())
+        } else {
+            Err(Error::RepositoryNotInitialized(self.name.clone()))
+        }
+    }
+
+    async fn read<T: DeserializeOwned>(&self, file: &str) -> Result<T, Error> {
+        Ok(serde_json::from_slice(
+            &fs::read(self.dir.join(file)).await?,
+        )?)
+    }
+
+    /// Replace the file at once so that clients never read half of it
+    async fn write<T: Serialize>(&self, file: &str, value: &T) -> Result<(), Error> {
+        let tmp = self.dir.join(format!(".{}.tmp", file));
+        fs::write(&tmp, serde_json::to_vec_pretty(value)?).await?;
+        fs::rename(tmp, self.dir.join(file)).await?;
+        Ok(())
+    }
+}
+
+/// Binary being uploaded to a repository, see `UpdateRepository::upload`
+pub struct Upload {
+    file: String,
+    info: UploadInfo,
+    info_path: PathBuf,
+    part: PathBuf,
+    target: PathBuf,
+    received: u64,
+    handle: fs::File,
+}
+
+impl Upload {
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    pub fn size(&self) -> u64 {
+        self.info.size
+    }
+
+    /// Append a chunk, which must start where the previous one ended. It is
+    /// on disk when this returns, so that an interrupted upload resumes
+    /// after it.
+    pub async fn write(&mut self, offset: u64, data: &[u8]) -> Result<(), Error> {
+        if offset != self.received {
+            return Err(Error::UploadOffset(self.file.clone(), self.received));
+        }
+        if self.received + data.len() as u64 > self.info.size {
+            return Err(Error::UploadTooLarge(self.file.clone(), self.info.size));
+        }
+        self.handle.write_all(data).await?;
+        self.handle.flush().await?;
+        self.received += data.len() as u64;
+        Ok(())
+    }
+
+    /// Once every byte is received, check the checksum and move the file to
+    /// the binaries of the repository. Returns whether the upload completed,
+    /// a file that does not match its checksum is dropped.
+    pub async fn finish(self) -> Result<bool, Error> {
+        self.handle.sync_all().await?;
+        if self.received < self.info.size {
+            return Ok(false);
+        }
+        let mut data = fs::File::open(&self.part).await?;
+        let mut digest = ring::digest::Context::new(&ring::digest::SHA256);
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = data.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            digest.update(&buffer[..read]);
+        }
+        let sha256: String = digest
+            .finish()
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        fs::remove_file(&self.info_path).await?;
+        if sha256 != self.info.sha256 {
+            fs::remove_file(&self.part).await?;
+            return Err(Error::ChecksumMismatch(self.file));
+        }
+        fs::rename(&self.part, &self.target).await?;
+        Ok(true)
+    }
+}
+
+/// Read `<name>.metadata` in `dir`
+async fn package_metadata(dir: &Path, name: &str) -> Result<PackageMetadata, Error> {
+    match fs::read(dir.join(format!("{}.{}", name, METADATA_EXTENSION))).await {
+        Ok(content) => Ok(serde_json::from_slice(&content)?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            Err(Error::PackageNotFound(name.to_string()))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Follow a rename of the repository row, if it was initialized
+pub async fn rename(root: &Path, name: &str, new_name: &str) -> Result<(), Error> {
+    let (from, to) = (root.join(name), root.join(new_name));
+    if !fs::try_exists(&from).await? {
+        return Ok(());
+    }
+    if fs::try_exists(&to).await? {
+        return Err(Error::RepositoryExists(new_name.to_string()));
+    }
+    Ok(fs::rename(from, to).await?)
+}
+
+/// Delete the files of a deleted repository
+pub async fn remove(root: &Path, name: &str) -> Result<(), Error> {
+    let dir = root.join(name);
+    if fs::try_exists(&dir).await? {
+        fs::remove_dir_all(dir).await?;
+    }
+    Ok(())
+}
+
+/// Repository, package and file names are single path components made of
+/// letters, digits, `-`, `_` and `.`, not starting with a dot
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+async fn dir_size(dir: &Path) -> Result<u64, Error> {
+    let mut size = 0;
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = fs::read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                size += metadata.len();
+            }
+        }
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &[u8] = b"binary uploaded in several chunks";
+
+    /// Initialized repository in the temporary directory, `name` is unique
+    /// per test
+    async fn repository(name: &str) -> UpdateRepository {
+        let root = std::env::temp_dir().join(format!("lucle-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let repository = UpdateRepository::open(&root, "game").unwrap();
+        repository.init().await.unwrap();
+        repository
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        ring::digest::digest(&ring::digest::SHA256, data)
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    async fn start(repository: &UpdateRepository, data: &[u8]) -> Result<Upload, Error> {
+        repository
+            .upload("game.bin", data.len() as u64, &sha256(data), 1024)
+            .await
+    }
+
+    #[test]
+    fn names_are_single_path_components() {
+        for name in ["game", "game-1.0_beta", "v2.bin", "a..b"] {
+            assert!(is_valid_name(name), "{}", name);
+        }
+        for name in [
+            "", ".", "..", ".staging", "a/b", "../game", "a\\b", "gäme", "a b",
+        ] {
+            assert!(!is_valid_name(name), "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn upload_is_staged_until_complete() {
+        let repository = repository("upload-complete").await;
+        let mut upload = start(&repository, DATA).await.unwrap();
+        upload.write(0, &DATA[..10]).await.unwrap();
+        upload.write(10, &DATA[10..]).await.unwrap();
+        assert_eq!(upload.received(), DATA.len() as u64);
+        assert!(repository
+            .status()
+            .await
+            .unwrap()
+            .available_binaries
+            .is_empty());
+
+        assert!(upload.finish().await.unwrap());
+        let binary = repository.dir.join(BINARIES_DIR).join("game.bin");
+        assert_eq!(std::fs::read(binary).unwrap(), DATA);
+        assert_eq!(
+            repository.uploaded("game.bin").await.unwrap(),
+            (DATA.len() as u64, true)
+        );
+        assert!(!repository.staging("game.bin", "part").exists());
+        assert!(!repository.staging("game.bin", "upload").exists());
+        assert!(matches!(
+            start(&repository, DATA).await,
+            Err(Error::BinaryExists(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn interrupted_upload_resumes() {
+        let repository = repository("upload-resume").await;
+        let mut upload = start(&repository, DATA).await.unwrap();
+        upload.write(0, &DATA[..10]).await.unwrap();
+        assert!(!upload.finish().await.unwrap());
+        assert_eq!(repository.uploaded("game.bin").await.unwrap(), (10, false));
+
+        let mut upload = start(&repository, DATA).await.unwrap();
+        assert_eq!(upload.received(), 10);
+        assert!(matches!(
+            upload.write(0, DATA).await,
+            Err(Error::UploadOffset(_, 10))
+        ));
+        upload.write(10, &DATA[10..]).await.unwrap();
+        assert!(upload.finish().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn another_file_restarts_the_upload() {
+        let repository = repository("upload-restart").await;
+        let mut upload = start(&repository, DATA).await.unwrap();
+        upload.write(0, &DATA[..10]).await.unwrap();
+        assert!(!upload.finish().await.unwrap());
+
+        let other = b"another binary";
+        let upload = start(&repository, other).await.unwrap();
+        assert_eq!(upload.received(), 0);
+        assert_eq!(upload.size(), other.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn corrupted_upload_is_dropped() {
+        let repository = repository("upload-corrupted").await;
+        let mut upload = repository
+            .upload("game.bin", DATA.len() as u64, &sha256(b"other"), 1024)
+            .await
+            .unwrap();
+        upload.write(0, DATA).await.unwrap();
+        assert!(matches!(
+            upload.finish().await,
+            Err(Error::ChecksumMismatch(_))
+        ));
+        assert_eq!(repository.uploaded("game.bin").await.unwrap(), (0, false));
+        assert!(!repository.dir.join(BINARIES_DIR).join("game.bin").exists());
+    }
+
+    #[tokio::test]
+    async fn invalid_uploads_are_refused() {
+        let repository = repository("upload-invalid").await;
+        let checksum = sha256(DATA);
+        for file in ["../game.bin", ".staging", "a/b"] {
+            let result = repository.upload(file, 1, &checksum, 1024).await;
+            assert!(matches!(result, Err(Error::InvalidFileName(_))), "{}", file);
+        }
+        assert!(matches!(
+            repository.upload("game.bin", 2048, &checksum, 1024).await,
+            Err(Error::UploadTooLarge(..))
+        ));
+        assert!(matches!(
+            repository
+                .upload("game.bin", 1, "not a checksum", 1024)
+                .await,
+            Err(Error::InvalidChecksum(_))
+        ));
+
+        let mut upload = start(&repository, DATA).await.unwrap();
+        let too_much = [DATA, b"!"].concat();
+        assert!(matches!(
+            upload.write(0, &too_much).await,
+            Err(Error::UploadTooLarge(..))
+        ));
+    }
+
+    async fn upload_file(repository: &UpdateRepository, file: &str, data: &[u8]) {
+        let mut upload = repository
+            .upload(file, data.len() as u64, &sha256(data), 1024)
+            .await
+            .unwrap();
+        upload.write(0, data).await.unwrap();
+        assert!(upload.finish().await.unwrap());
+    }
+
+    /// Metadata of a package of `DATA` installing version 1.0
+    fn metadata() -> Vec<u8> {
+        serde_json::to_vec(&PackageMetadata {
+            from: None,
+            to: "1.0".to_string(),
+            size: DATA.len() as u64,
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn uploaded_packages_are_registered() {
+        let repository = repository("upload-package").await;
+        let keypair = minisign::KeyPair::generate_unencrypted_keypair().unwrap();
+        repository
+            .add_trusted_key(&keypair.pk.to_base64())
+            .await
+            .unwrap();
+        repository.register_version("1.0", "").await.unwrap();
+        let signature = minisign::sign(Some(&keypair.pk), &keypair.sk, DATA, None, None)
+            .unwrap()
+            .into_string();
+        upload_file(&repository, "game-1.0", DATA).await;
+        upload_file(&repository, "game-1.0.minisig", signature.as_bytes()).await;
+        // Not a package until its metadata is there
+        assert!(matches!(
+            repository.register_package("game-1.0", |_, _| {}).await,
+            Err(Error::PackageNotFound(_))
+        ));
+        upload_file(&repository, "game-1.0.metadata", &metadata()).await;
+
+        repository
+            .register_package("game-1.0", |_, _| {})
+            .await
+            .unwrap();
+        let status = repository.status().await.unwrap();
+        assert_eq!(status.packages, ["game-1.0"]);
+        assert!(status.available_binaries.is_empty());
+        assert_eq!(
+            std::fs::read(repository.dir.join("game-1.0")).unwrap(),
+            DATA
+        );
+        assert!(matches!(
+            repository.register_package("game-1.0", |_, _| {}).await,
+            Err(Error::PackageExists(_))
+        ));
+        assert!(matches!(
+            repository.register_package(PACKAGES_FILE, |_, _| {}).await,
+            Err(Error::InvalidFileName(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn packages_signed_by_untrusted_keys_stay_uploaded() {
+        let repository = repository("upload-package-untrusted").await;
+        let keypair = minisign::KeyPair::generate_unencrypted_keypair().unwrap();
+        repository.register_version("1.0", "").await.unwrap();
+        let signature = minisign::sign(Some(&keypair.pk), &keypair.sk, DATA, None, None)
+            .unwrap()
+            .into_string();
+        upload_file(&repository, "game-1.0", DATA).await;
+        upload_file(&repository, "game-1.0.minisig", signature.as_bytes()).await;
+        upload_file(&repository, "game-1.0.metadata", &metadata()).await;
+
+        assert!(matches!(
+            repository.register_package("game-1.0", |_, _| {}).await,
+            Err(Error::UntrustedSignature(_))
+        ));
+        let status = repository.status().await.unwrap();
+        assert!(status.packages.is_empty());
+        assert_eq!(
+            status.available_binaries,
+            ["game-1.0", "game-1.0.metadata", "game-1.0.minisig"]
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use super::diesel::DieselStorage;
+use super::surrealdb::SurrealStorage;
+use crate::errors::Error;
+use crate::models::{
+    NewUser, Page, PageRevision, Plugin, RefreshToken, Repository, User, UsersRepositories,
+};
+use crate::DbType;
+use std::sync::{Arc, RwLock};
+
+#[tonic::async_trait]
+pub trait Storage: Send + Sync {
+    async fn create_user(&self, user: NewUser) -> Result<(), Error>;
+    /// Insert the user only when there is no user yet, in one transaction.
+    /// Returns false when another user exists.
+    async fn create_first_user(&self, user: NewUser) -> Result<bool, Error>;
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, Error>;
+    async fn get_user_by_email(&self, email: &str) -> Result<Option<User>, Error>;
+    async fn get_user_by_id(&self, user_id: i32) -> Result<Option<User>, Error>;
+    async fn count_users(&self) -> Result<i64, Error>;
+    async fn list_users(&self) -> Result<Vec<User>, Error>;
+    /// Store a new password hash and clear any pending reset token
+    async fn set_password(&self, user_id: i32, password: String) -> Result<(), Error>;
+    async fn set_reset_token(&self, email: &str, token: Option<String>) -> Result<(), Error>;
+    /// Store a new password hash if `token` is the pending reset token and
+    /// clear it, returns false when it is not
+    async fn redeem_reset_token(
+        &self,
+        user_id: i32,
+        token: &str,
+        password: String,
+    ) -> Result<bool, Error>;
+    async fn create_refresh_token(&self, token: RefreshToken) -> Result<(), Error>;
+    async fn get_refresh_token(&self, id: &str) -> Result<Option<RefreshToken>, Error>;
+    /// Returns false when the token was already revoked
+    async fn revoke_refresh_token(&self, id: &str) -> Result<bool, Error>;
+    async fn revoke_refresh_tokens_by_user(&self, user_id: i32) -> Result<(), Error>;
+    /// Insert the repository and the membership of its owner in one transaction
+    async fn create_repository(
+        &self,
+        repository: Repository,
+        owner: UsersRepositories,
+    ) -> Result<(), Error>;
+    async fn get_repository(&self, name: &str) -> Result<Option<Repository>, Error>;
+    /// Delete the repository and its memberships in one transaction
+    async fn delete_repository(&self, name: &str) -> Result<(), Error>;
+    /// Rename the repository and its memberships in one transaction
+    async fn rename_repository(&self, name: &str, new_name: &str) -> Result<(), Error>;
+    /// Make `owner_id` the owner of the repository with write access, in one
+    /// transaction
+    async fn transfer_repository(&self, name: &str, owner_id: i32) -> Result<(), Error>;
+    async fn add_member(&self, membership: UsersRepositories) -> Result<(), Error>;
+    /// Change the permission of an existing membership
+    async fn update_member(&self, membership: UsersRepositories) -> Result<(), Error>;
+    async fn remove_member(&self, user_id: i32, repository_name: &str) -> Result<(), Error>;
+    async fn list_memberships_by_user(&self, user_id: i32)
+        -> Result<Vec<UsersRepositories>, Error>;
+    async fn list_members(&self, repository_name: &str) -> Result<Vec<UsersRepositories>, Error>;
+    async fn list_plugins(&self) -> Result<Vec<Plugin>, Error>;
+    /// Store whether the plugin is enabled, adding it installed now when it
+    /// has no state yet
+    async fn set_plugin_enabled(&self, name: &str, enabled: bool) -> Result<(), Error>;
+    async fn delete_plugin(&self, name: &str) -> Result<(), Error>;
+    /// Insert the page and its first revision in one transaction
+    async fn create_page(&self, page: Page, revision: PageRevision) -> Result<(), Error>;
+    async fn get_page(&self, slug: &str) -> Result<Option<Page>, Error>;
+    async fn list_pages(&self) -> Result<Vec<Page>, Error>;
+    /// Replace the page at `slug` and add `revision` when its content changed,
+    /// in one transaction. The page and its revisions move when `page.slug`
+    /// differs. Nothing changes and `false` is returned when the page is no
+    /// longer at `read_revision`, another editor saved it meanwhile.
+    async fn update_page(
+        &self,
+        slug: &str,
+        read_revision: i32,
+        page: Page,
+        revision: Option<PageRevision>,
+    ) -> Result<bool, Error>;
+    /// Delete the page and its revisions in one transaction
+    async fn delete_page(&self, slug: &str) -> Result<(), Error>;
+    async fn get_page_revision(
+        &self,
+        slug: &str,
+        revision: i32,
+    ) -> Result<Option<PageRevision>, Error>;
+    /// Revisions of a page, oldest first
+    async fn list_page_revisions(&self, slug: &str) -> Result<Vec<PageRevision>, Error>;
+}
+
+/// Storage shared by the gRPC services. The install wizard replaces it when
+/// it creates a database, so each call takes its own handle on the current one.
+#[derive(Clone)]
+pub struct SharedStorage(Arc<RwLock<Option<Arc<dyn Storage>>>>);
+
+impl SharedStorage {
+    pub fn new(storage: Option<Arc<dyn Storage>>) -> Self {
+        Self(Arc::new(RwLock::new(storage)))
+    }
+
+    pub fn get(&self) -> Result<Arc<dyn Storage>, Error> {
+        match self.0.read().unwrap().clone() {
+            Some(storage) => Ok(storage),
+            None => {
+                tracing::error!("{}", Error::NoDatabase);
+                Err(Error::NoDatabase)
+            }
+        }
+    }
+
+    pub fn current(&self) -> Option<Arc<dyn Storage>> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, storage: Option<Arc<dyn Storage>>) {
+        *self.0.write().unwrap() = storage;
+    }
+}
+
+pub async fn connect(db: DbType) -> Result<Option<Arc<dyn Storage>>, Error> {
+    let storage: Arc<dyn Storage> = match db {
+        DbType::Mysql(url) => Arc::new(DieselStorage::mysql(&url)?),
+        DbType::Postgresql(url) => Arc::new(DieselStorage::postgresql(&url)?),
+        DbType::Sqlite(url) => Arc::new(DieselStorage::sqlite(&url)?),
+        DbType::Surrealdb(path) => Arc::new(SurrealStorage::new(&path).await?),
+        DbType::NoDatabase => return Ok(None),
+    };
+    Ok(Some(storage))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::diesel::tests::sqlite_storage;
+    use crate::surrealdb::tests::surrealkv_storage;
+
+    /// A fresh database of each backend, SQLite standing for the diesel ones
+    pub async fn storages(name: &str) -> [Arc<dyn Storage>; 2] {
+        [
+            Arc::new(sqlite_storage(name).await),
+            Arc::new(surrealkv_storage(name).await),
+        ]
+    }
+}
@@ -1,9 +1,550 @@
-use surrealdb::engine::local::SurrealKV;
+use crate::errors::Error;
+use crate::models::{
+    NewUser, Page, PageRevision, Plugin, RefreshToken, Repository, User, UsersRepositories,
+};
+use crate::storage::Storage;
+use surrealdb::engine::local::{Db, SurrealKv};
 use surrealdb::Surreal;
 
-pub async fn create_database() -> surrealdb::Result<()> {
-    let db = Surreal::new::<SurrealKV>("./").await?;
-    db.use_ns("lucle").use_db("lucle").await?;
-    tracing::info!("Creating database lucle");
-    Ok(())
+/// Tables mirror the diesel schema. Users get an integer record id taken from
+/// a counter so that `User::id` and `users_repositories.user_id` keep the same
+/// meaning on every backend, and memberships are keyed by
+/// `[user_id, repository_name]` like the composite primary key in SQL.
+const SCHEMA: &str = "
+    DEFINE TABLE IF NOT EXISTS user SCHEMALESS;
+    DEFINE INDEX IF NOT EXISTS user_username ON user FIELDS username UNIQUE;
+    DEFINE INDEX IF NOT EXISTS user_email ON user FIELDS email UNIQUE;
+    DEFINE TABLE IF NOT EXISTS repository SCHEMALESS;
+    DEFINE INDEX IF NOT EXISTS repository_name ON repository FIELDS name UNIQUE;
+    DEFINE TABLE IF NOT EXISTS users_repositories SCHEMALESS;
+    DEFINE TABLE IF NOT EXISTS refresh_token SCHEMALESS;
+    DEFINE INDEX IF NOT EXISTS refresh_token_user_id ON refresh_token FIELDS user_id;
+    DEFINE TABLE IF NOT EXISTS plugin SCHEMALESS;
+    DEFINE TABLE IF NOT EXISTS page SCHEMALESS;
+    DEFINE INDEX IF NOT EXISTS page_slug ON page FIELDS slug UNIQUE;
+    DEFINE TABLE IF NOT EXISTS page_revision SCHEMALESS;
+";
+
+pub struct SurrealStorage {
+    db: Surreal<Db>,
+}
+
+impl SurrealStorage {
+    pub async fn new(path: &str) -> Result<Self, Error> {
+        let db = Surreal::new::<SurrealKv>(path).await?;
+        db.use_ns("lucle").use_db("lucle").await?;
+        db.query(SCHEMA).await?.check()?;
+        Ok(Self { db })
+    }
+}
+
+#[tonic::async_trait]
+impl Storage for SurrealStorage {
+    async fn create_user(&self, user: NewUser) -> Result<(), Error> {
+        self.db
+            .query(
+                "BEGIN TRANSACTION;
+                LET $id = (UPSERT ONLY counter:user SET value += 1 RETURN VALUE value);
+                CREATE type::thing('user', $id) CONTENT $user;
+                COMMIT TRANSACTION;",
+            )
+            .bind(("user", user))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    /// Both transactions write the user counter, so the second one to commit
+    /// fails instead of creating another first user
+    async fn create_first_user(&self, user: NewUser) -> Result<bool, Error> {
+        let created: Option<bool> = self
+            .db
+            .query(
+                "BEGIN TRANSACTION;
+                LET $empty = array::len((SELECT id FROM user LIMIT 1)) == 0;
+                IF $empty {
+                    LET $id = (UPSERT ONLY counter:user SET value += 1 RETURN VALUE value);
+                    CREATE type::thing('user', $id) CONTENT $user;
+                };
+                RETURN $empty;
+                COMMIT TRANSACTION;",
+            )
+            .bind(("user", user))
+            .await?
+            // The transaction only returns the value of `RETURN`
+            .take(0)?;
+        Ok(created.unwrap_or(false))
+    }
+
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, Error> {
+        let user = self
+            .db
+            .query("SELECT *, record::id(id) AS id FROM user WHERE username = $username LIMIT 1")
+            .bind(("username", username.to_owned()))
+            .await?
+            .take(0)?;
+        Ok(user)
+    }
+
+    async fn get_user_by_email(&self, email: &str) -> Result<Option<User>, Error> {
+        let user = self
+            .db
+            .query("SELECT *, record::id(id) AS id FROM user WHERE email = $email LIMIT 1")
+            .bind(("email", email.to_owned()))
+            .await?
+            .take(0)?;
+        Ok(user)
+    }
+
+    async fn get_user_by_id(&self, user_id: i32) -> Result<Option<User>, Error> {
+        let user = self
+            .db
+            .query("SELECT *, record::id(id) AS id FROM ONLY type::thing('user', $user_id)")
+            .bind(("user_id", user_id))
+            .await?
+            .take(0)?;
+        Ok(user)
+    }
+
+    async fn count_users(&self) -> Result<i64, Error> {
+        let count: Option<i64> = self
+            .db
+            .query("SELECT count() FROM user GROUP ALL")
+            .await?
+            .take((0, "count"))?;
+        Ok(count.unwrap_or(0))
+    }
+
+    async fn list_users(&self) -> Result<Vec<User>, Error> {
+        let users = self
+            .db
+            .query("SELECT *, record::id(id) AS id FROM user ORDER BY id")
+            .await?
+            .take(0)?;
+        Ok(users)
+    }
+
+    async fn set_password(&self, user_id: i32, password: String) -> Result<(), Error> {
+        self.db
+            .query(
+                "UPDATE type::thing('user', $user_id)
+                SET password = $password, modified_at = $modified_at, reset_token = NONE",
+            )
+            .bind(("user_id", user_id))
+            .bind(("password", password))
+            .bind(("modified_at", chrono::Utc::now().naive_utc()))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn set_reset_token(&self, email: &str, token: Option<String>) -> Result<(), Error> {
+        self.db
+            .query("UPDATE user SET reset_token = $reset_token WHERE email = $email")
+            .bind(("reset_token", token))
+            .bind(("email", email.to_owned()))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn redeem_reset_token(
+        &self,
+        user_id: i32,
+        token: &str,
+        password: String,
+    ) -> Result<bool, Error> {
+        let updated: Vec<User> = self
+            .db
+            .query(
+                "UPDATE type::thing('user', $user_id)
+                SET password = $password, modified_at = $modified_at, reset_token = NONE
+                WHERE reset_token = $reset_token RETURN *, record::id(id) AS id",
+            )
+            .bind(("user_id", user_id))
+            .bind(("reset_token", token.to_owned()))
+            .bind(("password", password))
+            .bind(("modified_at", chrono::Utc::now().naive_utc()))
+            .await?
+            .take(0)?;
+        Ok(!updated.is_empty())
+    }
+
+    async fn create_refresh_token(&self, token: RefreshToken) -> Result<(), Error> {
+        self.db
+            .query("CREATE type::thing('refresh_token', $refresh_token.id) CONTENT $refresh_token")
+            .bind(("refresh_token", token))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn get_refresh_token(&self, id: &str) -> Result<Option<RefreshToken>, Error> {
+        let token = self
+            .db
+            .query("SELECT *, record::id(id) AS id FROM type::thing('refresh_token', $id)")
+            .bind(("id", id.to_owned()))
+            .await?
+            .take(0)?;
+        Ok(token)
+    }
+
+    async fn revoke_refresh_token(&self, id: &str) -> Result<bool, Error> {
+        let revoked: Vec<RefreshToken> = self
+            .db
+            .query(
+                "UPDATE type::thing('refresh_token', $id) SET revoked = true
+                WHERE revoked = false RETURN *, record::id(id) AS id",
+            )
+            .bind(("id", id.to_owned()))
+            .await?
+            .take(0)?;
+        Ok(!revoked.is_empty())
+    }
+
+    async fn revoke_refresh_tokens_by_user(&self, user_id: i32) -> Result<(), Error> {
+        self.db
+            .query("UPDATE refresh_token SET revoked = true WHERE user_id = $user_id")
+            .bind(("user_id", user_id))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn create_repository(
+        &self,
+        repository: Repository,
+        owner: UsersRepositories,
+    ) -> Result<(), Error> {
+        self.db
+            .query(
+                "BEGIN TRANSACTION;
+                CREATE repository CONTENT $repository;
+                CREATE type::thing('users_repositories', [$owner.user_id, $owner.repository_name])
+                CONTENT $owner;
+                COMMIT TRANSACTION;",
+            )
+            .bind(("repository", repository))
+            .bind(("owner", owner))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn get_repository(&self, name: &str) -> Result<Option<Repository>, Error> {
+        let repository = self
+            .db
+            .query("SELECT name, created_at, owner_id FROM repository WHERE name = $name LIMIT 1")
+            .bind(("name", name.to_owned()))
+            .await?
+            .take(0)?;
+        Ok(repository)
+    }
+
+    async fn delete_repository(&self, name: &str) -> Result<(), Error> {
+        self.db
+            .query(
+                "BEGIN TRANSACTION;
+                DELETE users_repositories WHERE repository_name = $name;
+                DELETE repository WHERE name = $name;
+                COMMIT TRANSACTION;",
+            )
+            .bind(("name", name.to_owned()))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    /// Membership ids contain the repository name, so they are recreated
+    async fn rename_repository(&self, name: &str, new_name: &str) -> Result<(), Error> {
+        self.db
+            .query(
+                "BEGIN TRANSACTION;
+                LET $members = (SELECT user_id, permission FROM users_repositories
+                    WHERE repository_name = $name);
+                DELETE users_repositories WHERE repository_name = $name;
+                FOR $member IN $members {
+                    CREATE type::thing('users_repositories', [$member.user_id, $new_name]) CONTENT {
+                        user_id: $member.user_id,
+                        repository_name: $new_name,
+                        permission: $member.permission,
+                    };
+                };
+                UPDATE repository SET name = $new_name WHERE name = $name;
+                COMMIT TRANSACTION;",
+            )
+            .bind(("name", name.to_owned()))
+            .bind(("new_name", new_name.to_owned()))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn transfer_repository(&self, name: &str, owner_id: i32) -> Result<(), Error> {
+        self.db
+            .query(
+                "BEGIN TRANSACTION;
+                UPDATE repository SET owner_id = $owner_id WHERE name = $name;
+                UPSERT type::thing('users_repositories', [$owner_id, $name]) CONTENT {
+                    user_id: $owner_id,
+                    repository_name: $name,
+                    permission: 'write',
+                };
+                COMMIT TRANSACTION;",
+            )
+            .bind(("name", name.to_owned()))
+            .bind(("owner_id", owner_id))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn add_member(&self, membership: UsersRepositories) -> Result<(), Error> {
+        self.db
+            .query(
+                "CREATE type::thing('users_repositories', [$membership.user_id, $membership.repository_name])
+                CONTENT $membership",
+            )
+            .bind(("membership", membership))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn update_member(&self, membership: UsersRepositories) -> Result<(), Error> {
+        self.db
+            .query(
+                "UPDATE type::thing('users_repositories', [$membership.user_id, $membership.repository_name])
+                SET permission = $membership.permission",
+            )
+            .bind(("membership", membership))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn remove_member(&self, user_id: i32, repository_name: &str) -> Result<(), Error> {
+        self.db
+            .query("DELETE type::thing('users_repositories', [$user_id, $repository_name])")
+            .bind(("user_id", user_id))
+            .bind(("repository_name", repository_name.to_owned()))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn list_memberships_by_user(
+        &self,
+        user_id: i32,
+    ) -> Result<Vec<UsersRepositories>, Error> {
+        let memberships = self
+            .db
+            .query(
+                "SELECT user_id, repository_name, permission FROM users_repositories
+                WHERE user_id = $user_id",
+            )
+            .bind(("user_id", user_id))
+            .await?
+            .take(0)?;
+        Ok(memberships)
+    }
+
+    async fn list_members(&self, repository_name: &str) -> Result<Vec<UsersRepositories>, Error> {
+        let memberships = self
+            .db
+            .query(
+                "SELECT user_id, repository_name, permission FROM users_repositories
+                WHERE repository_name = $repository_name",
+            )
+            .bind(("repository_name", repository_name.to_owned()))
+            .await?
+            .take(0)?;
+        Ok(memberships)
+    }
+
+    async fn list_plugins(&self) -> Result<Vec<Plugin>, Error> {
+        let plugins = self
+            .db
+            .query("SELECT name, enabled, installed_at FROM plugin ORDER BY name")
+            .await?
+            .take(0)?;
+        Ok(plugins)
+    }
+
+    async fn set_plugin_enabled(&self, name: &str, enabled: bool) -> Result<(), Error> {
+        self.db
+            .query(
+                "UPSERT type::thing('plugin', $name)
+                SET name = $name, enabled = $enabled, installed_at = installed_at ?? $installed_at",
+            )
+            .bind(("name", name.to_owned()))
+            .bind(("enabled", enabled))
+            .bind(("installed_at", chrono::Utc::now().naive_utc()))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn delete_plugin(&self, name: &str) -> Result<(), Error> {
+        self.db
+            .query("DELETE type::thing('plugin', $name)")
+            .bind(("name", name.to_owned()))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn create_page(&self, page: Page, revision: PageRevision) -> Result<(), Error> {
+        self.db
+            .query(
+                "BEGIN TRANSACTION;
+                CREATE page CONTENT $page;
+                CREATE type::thing('page_revision', [$revision.page_slug, $revision.revision])
+                CONTENT $revision;
+                COMMIT TRANSACTION;",
+            )
+            .bind(("page", page))
+            .bind(("revision", revision))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn get_page(&self, slug: &str) -> Result<Option<Page>, Error> {
+        let page = self
+            .db
+            .query("SELECT * OMIT id FROM page WHERE slug = $slug LIMIT 1")
+            .bind(("slug", slug.to_owned()))
+            .await?
+            .take(0)?;
+        Ok(page)
+    }
+
+    async fn list_pages(&self) -> Result<Vec<Page>, Error> {
+        let pages = self
+            .db
+            .query("SELECT * OMIT id FROM page ORDER BY slug")
+            .await?
+            .take(0)?;
+        Ok(pages)
+    }
+
+    /// Revision ids contain the page slug, so they are recreated when it moves
+    async fn update_page(
+        &self,
+        slug: &str,
+        read_revision: i32,
+        page: Page,
+        revision: Option<PageRevision>,
+    ) -> Result<bool, Error> {
+        let saved: Result<Option<bool>, _> = self
+            .db
+            .query(
+                "BEGIN TRANSACTION;
+                LET $saved = (SELECT VALUE revision FROM page WHERE slug = $slug)
+                    == [$read_revision];
+                IF $saved {
+                    UPDATE page CONTENT $page WHERE slug = $slug;
+                    IF $page.slug != $slug {
+                        LET $revisions = (SELECT * OMIT id FROM page_revision
+                            WHERE page_slug = $slug);
+                        DELETE page_revision WHERE page_slug = $slug;
+                        FOR $old IN $revisions {
+                            CREATE type::thing('page_revision', [$page.slug, $old.revision])
+                            CONTENT {
+                                page_slug: $page.slug,
+                                revision: $old.revision,
+                                title: $old.title,
+                                body: $old.body,
+                                author_id: $old.author_id,
+                                created_at: $old.created_at,
+                            };
+                        };
+                    };
+                    IF $revision {
+                        CREATE type::thing('page_revision', [$revision.page_slug, $revision.revision])
+                        CONTENT $revision;
+                    };
+                };
+                RETURN $saved;
+                COMMIT TRANSACTION;",
+            )
+            .bind(("slug", slug.to_owned()))
+            .bind(("read_revision", read_revision))
+            .bind(("page", page))
+            .bind(("revision", revision))
+            .await?
+            // The transaction only returns the value of `RETURN`
+            .take(0);
+        match saved {
+            Ok(saved) => Ok(saved.unwrap_or(false)),
+            // Another editor committed first, SurrealKV transactions are
+            // optimistic
+            Err(err) if is_write_conflict(&err) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn delete_page(&self, slug: &str) -> Result<(), Error> {
+        self.db
+            .query(
+                "BEGIN TRANSACTION;
+                DELETE page_revision WHERE page_slug = $slug;
+                DELETE page WHERE slug = $slug;
+                COMMIT TRANSACTION;",
+            )
+            .bind(("slug", slug.to_owned()))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn get_page_revision(
+        &self,
+        slug: &str,
+        revision: i32,
+    ) -> Result<Option<PageRevision>, Error> {
+        let revision = self
+            .db
+            .query("SELECT * OMIT id FROM ONLY type::thing('page_revision', [$slug, $revision])")
+            .bind(("slug", slug.to_owned()))
+            .bind(("revision", revision))
+            .await?
+            .take(0)?;
+        Ok(revision)
+    }
+
+    async fn list_page_revisions(&self, slug: &str) -> Result<Vec<PageRevision>, Error> {
+        let revisions = self
+            .db
+            .query("SELECT * OMIT id FROM page_revision WHERE page_slug = $slug ORDER BY revision")
+            .bind(("slug", slug.to_owned()))
+            .await?
+            .take(0)?;
+        Ok(revisions)
+    }
+}
+
+/// The transaction did not commit because a concurrent one changed the same
+/// records, it can be retried
+fn is_write_conflict(err: &surrealdb::Error) -> bool {
+    matches!(
+        err,
+        surrealdb::Error::Db(surrealdb::error::Db::QueryNotExecutedDetail { message })
+            if *message == surrealdb::error::Db::TxRetryable.to_string()
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// SurrealKV database in the temporary directory, `name` is unique per
+    /// test
+    pub async fn surrealkv_storage(name: &str) -> SurrealStorage {
+        let path =
+            std::env::temp_dir().join(format!("lucle-{}-{}.surrealkv", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        SurrealStorage::new(path.to_str().unwrap()).await.unwrap()
+    }
 }
@@ -1,62 +1,11 @@
-use base64::{engine::general_purpose, Engine as _};
-use jsonwebtoken::{encode, get_current_timestamp, Algorithm, EncodingKey};
-use lettre::{
-    message::{header, MultiPart, SinglePart},
-    FileTransport, Message, Transport,
-};
 use rcgen::{DnType, KeyPair, KeyUsagePurpose};
-use serde::{Deserialize, Serialize};
 use std::{
     fs::{self, File},
     io::{Result, Write},
+    path::Path,
 };
-use tera::{Context, Tera};
 use time::{Duration, OffsetDateTime};
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    email: String,
-    exp: u64,
-    scope: String,
-}
-
-pub fn send_mail(from: &str, dest: &str, subject: &str, _body: &str) {
-    let context = Context::new();
-    let tera = match Tera::new("templates") {
-        Ok(t) => t,
-        Err(e) => {
-            println!("Parsing error(s): {}", e);
-            ::std::process::exit(1);
-        }
-    };
-
-    let rendered_content = tera.render("email.html", &context).unwrap();
-
-    let email = Message::builder()
-        .from(from.parse().unwrap())
-        .to(dest.parse().unwrap())
-        .subject(subject)
-        .multipart(
-            MultiPart::alternative()
-                .singlepart(
-                    SinglePart::builder()
-                        .header(header::ContentType::TEXT_PLAIN)
-                        .body(String::from("Hello from Lettre! A mailer library for Rust")), // Every message should have a plain text fallback.
-                )
-                .singlepart(
-                    SinglePart::builder()
-                        .header(header::ContentType::TEXT_HTML)
-                        .body(rendered_content),
-                ),
-        )
-        .unwrap();
-
-    let mailer = FileTransport::new("./");
-
-    // Store the message when you're ready.
-    mailer.send(&email).expect("failed to deliver message");
-}
+use tokio::sync::watch;
 
 pub struct Pki {
     pub ca_cert: rcgen::CertifiedKey,
@@ -122,28 +71,59 @@ fn validity_period() -> (OffsetDateTime, OffsetDateTime) {
     (yesterday, tomorrow)
 }
 
-pub fn write_pem(path: &str, pem: &str) -> Result<()> {
+/// Generate the CA and server certificates in `dir` unless they all exist
+/// already. Returns whether new certificates were written.
+pub fn init_pki(dir: &Path, force: bool) -> Result<bool> {
+    let ca_cert_path = dir.join("ca_cert.pem");
+    let server_cert_path = dir.join("server_cert.pem");
+    let server_key_path = dir.join("server_private_key.pem");
+    if !force && ca_cert_path.exists() && server_cert_path.exists() && server_key_path.exists() {
+        return Ok(false);
+    }
+
+    let pki = Pki::new();
+    fs::create_dir_all(dir)?;
+    write_pem(&ca_cert_path, &pki.ca_cert.cert.pem())?;
+    write_pem(&server_cert_path, &pki.server_cert.cert.pem())?;
+    write_pem(&server_key_path, &pki.server_cert.key_pair.serialize_pem())?;
+    Ok(true)
+}
+
+pub fn write_pem(path: &Path, pem: &str) -> Result<()> {
     let mut file = File::create(path)?;
     file.write_all(pem.as_bytes())?;
     Ok(())
 }
 
-pub fn generate_jwt(username: String, email: String) -> String {
-    let encoded_pkcs8 = fs::read_to_string("pkey").unwrap();
-    let decoded_pkcs8 = general_purpose::STANDARD.decode(encoded_pkcs8).unwrap();
-    let encoding_key = EncodingKey::from_ec_der(&decoded_pkcs8);
-
-    let claims = Claims {
-        sub: username,
-        email,
-        exp: get_current_timestamp(),
-        scope: "test".to_string(),
+/// Resolve on Ctrl+C or, on unix, SIGTERM
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!("Unable to listen for Ctrl+C: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                tracing::error!("Unable to listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
     };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
 
-    encode(
-        &jsonwebtoken::Header::new(Algorithm::ES256),
-        &claims,
-        &encoding_key,
-    )
-    .unwrap()
+/// Resolve once the process is shutting down, every server stops on it
+pub async fn shutdown_requested(mut shutdown: watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|stop| *stop).await;
 }
@@ -0,0 +1,639 @@
+use crate::errors::Error;
+use crate::plugin::{PluginCall, PluginEvent, PluginReply, ABI_VERSION};
+use crate::storage::SharedStorage;
+use serde_json::json;
+use std::{collections::HashMap, path::Path, sync::Mutex};
+use tokio::{runtime::Handle, sync::broadcast};
+use wasmi::{
+    Caller, Config, Engine, Extern, Instance, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder,
+};
+
+// WebAssembly interface. A module imports the functions of the `lucle`
+// module defined in `link` and exports:
+//
+// - its `memory`
+// - `i32 lucle_plugin_abi_version()`, returning `ABI_VERSION`
+// - `i32 lucle_plugin_register_v1()`, called once after loading to register
+//   routes and hooks, returning 0 on success
+// - `i32 lucle_plugin_handle_v1(i32 route)`, serving a request of the route
+//   id returned when it was registered, returning its status
+// - `lucle_plugin_hook_v1(i32 event)`, when it registers hooks
+//
+// Functions handing data to the module take a buffer `(ptr, capacity)` and
+// return the length of the data, which is only written when it fits. Every
+// function returns -1 on error.
+
+const HOST_MODULE: &str = "lucle";
+
+/// Resources of a WebAssembly plugin
+#[derive(Debug, Clone, Copy)]
+pub struct WasmLimits {
+    /// Fuel of each call into the module, about one per instruction
+    pub fuel: u64,
+    /// Largest linear memory of the module, in bytes
+    pub max_memory: usize,
+}
+
+/// Routes and hooks registered by a module, by route id
+#[derive(Default)]
+pub struct WasmRegistration {
+    pub grpc: Vec<(String, u32)>,
+    pub http: Vec<(String, String, u32)>,
+    pub hooks: Vec<u32>,
+    pub errors: Vec<String>,
+    next_route: u32,
+}
+
+impl WasmRegistration {
+    fn route(&mut self) -> u32 {
+        self.next_route += 1;
+        self.next_route
+    }
+}
+
+/// Read-only access to lucle given to the module
+struct HostState {
+    plugin: String,
+    config: HashMap<String, String>,
+    storage: SharedStorage,
+    runtime: Handle,
+    events: broadcast::Sender<(String, PluginEvent)>,
+    limits: StoreLimits,
+    /// Set while the module registers
+    registration: Option<WasmRegistration>,
+    /// Set while the module serves a request
+    call: Option<PluginCall>,
+    reply: PluginReply,
+}
+
+/// What the plugin host shares with its modules
+pub struct WasmHost {
+    pub storage: SharedStorage,
+    pub events: broadcast::Sender<(String, PluginEvent)>,
+    pub limits: WasmLimits,
+}
+
+/// Instantiated module, serving one call at a time
+pub struct WasmModule {
+    store: Mutex<Store<HostState>>,
+    instance: Instance,
+    fuel: u64,
+}
+
+impl WasmModule {
+    /// Compile and instantiate the module, then let it register its routes
+    /// and hooks. Blocks, the host API waits for the database.
+    pub fn load(
+        plugin: &str,
+        path: &Path,
+        config: HashMap<String, String>,
+        host: WasmHost,
+    ) -> Result<(Self, WasmRegistration), Error> {
+        let failed = |err: wasmi::Error| Error::Plugin(format!("{}: {}", plugin, err));
+        let mut engine_config = Config::default();
+        engine_config.consume_fuel(true);
+        let engine = Engine::new(&engine_config);
+        let module = Module::new(&engine, std::fs::read(path)?).map_err(failed)?;
+
+        let state = HostState {
+            plugin: plugin.to_string(),
+            config,
+            storage: host.storage,
+            runtime: Handle::current(),
+            events: host.events,
+            limits: StoreLimitsBuilder::new()
+                .memory_size(host.limits.max_memory)
+                .instances(1)
+                .build(),
+            registration: None,
+            call: None,
+            reply: PluginReply::default(),
+        };
+        let mut store = Store::new(&engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(host.limits.fuel).map_err(failed)?;
+        let linker = link(&engine).map_err(failed)?;
+        let instance = linker
+            .instantiate_and_start(&mut store, &module)
+            .map_err(failed)?;
+
+        let found = instance
+            .get_typed_func::<(), i32>(&store, "lucle_plugin_abi_version")
+            .and_then(|abi_version| abi_version.call(&mut store, ()))
+            .map_err(failed)?;
+        if found as u32 != ABI_VERSION {
+            return Err(Error::PluginAbiVersion(found as u32, ABI_VERSION));
+        }
+        let register = instance
+            .get_typed_func::<(), i32>(&store, &format!("lucle_plugin_register_v{}", ABI_VERSION))
+            .map_err(failed)?;
+        store.data_mut().registration = Some(WasmRegistration::default());
+        let code = register.call(&mut store, ()).map_err(failed)?;
+        let registration = store.data_mut().registration.take().unwrap_or_default();
+        if code != 0 {
+            return Err(Error::Plugin(format!("Registration failed with {}", code)));
+        }
+        if !registration.errors.is_empty() {
+            return Err(Error::Plugin(registration.errors.join(", ")));
+        }
+        let exported = |name: &str| instance.get_func(&store, name).is_some();
+        let has_routes = !registration.grpc.is_empty() || !registration.http.is_empty();
+        if has_routes && !exported(&format!("lucle_plugin_handle_v{}", ABI_VERSION)) {
+            return Err(Error::Plugin(format!(
+                "{}: routes registered without lucle_plugin_handle_v{}",
+                plugin, ABI_VERSION
+            )));
+        }
+        if !registration.hooks.is_empty()
+            && !exported(&format!("lucle_plugin_hook_v{}", ABI_VERSION))
+        {
+            return Err(Error::Plugin(format!(
+                "{}: hooks registered without lucle_plugin_hook_v{}",
+                plugin, ABI_VERSION
+            )));
+        }
+
+        let module = Self {
+            store: Mutex::new(store),
+            instance,
+            fuel: host.limits.fuel,
+        };
+        Ok((module, registration))
+    }
+
+    /// Serve a request of `route` with a fresh supply of fuel. Blocks.
+    pub fn call(&self, route: u32, call: PluginCall) -> Result<PluginReply, Error> {
+        let mut store = self.store.lock().unwrap();
+        let plugin = store.data().plugin.clone();
+        let failed = |err: wasmi::Error| Error::Plugin(format!("{}: {}", plugin, err));
+        store.set_fuel(self.fuel).map_err(failed)?;
+        let handle = self
+            .instance
+            .get_typed_func::<i32, i32>(&*store, &format!("lucle_plugin_handle_v{}", ABI_VERSION))
+            .map_err(failed)?;
+        store.data_mut().call = Some(call);
+        store.data_mut().reply = PluginReply::default();
+        let status = handle.call(&mut *store, route as i32);
+        store.data_mut().call = None;
+        let mut reply = std::mem::take(&mut store.data_mut().reply);
+        reply.status = status.map_err(failed)? as u32;
+        Ok(reply)
+    }
+
+    /// Run the hook of the module on `event`. Blocks.
+    pub fn hook(&self, event: u32) -> Result<(), Error> {
+        let mut store = self.store.lock().unwrap();
+        let plugin = store.data().plugin.clone();
+        let failed = |err: wasmi::Error| Error::Plugin(format!("{}: {}", plugin, err));
+        store.set_fuel(self.fuel).map_err(failed)?;
+        self.instance
+            .get_typed_func::<i32, ()>(&*store, &format!("lucle_plugin_hook_v{}", ABI_VERSION))
+            .and_then(|hook| hook.call(&mut *store, event as i32))
+            .map_err(failed)
+    }
+}
+
+fn memory(caller: &Caller<'_, HostState>) -> Option<Memory> {
+    caller.get_export("memory").and_then(Extern::into_memory)
+}
+
+/// Copy `len` bytes at `ptr` out of the module memory
+fn read(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Option<Vec<u8>> {
+    let memory = memory(caller)?;
+    let (ptr, len) = (usize::try_from(ptr).ok()?, usize::try_from(len).ok()?);
+    if ptr.checked_add(len)? > memory.data_size(caller) {
+        return None;
+    }
+    let mut data = vec![0; len];
+    memory.read(caller, ptr, &mut data).ok()?;
+    Some(data)
+}
+
+fn read_string(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Option<String> {
+    String::from_utf8(read(caller, ptr, len)?).ok()
+}
+
+/// Hand `data` to the module in the buffer at `ptr`, if it fits
+fn write(caller: &mut Caller<'_, HostState>, ptr: i32, capacity: i32, data: &[u8]) -> i32 {
+    let (Ok(len), Ok(offset)) = (i32::try_from(data.len()), usize::try_from(ptr)) else {
+        return -1;
+    };
+    if len > capacity {
+        return len;
+    }
+    match memory(caller).map(|memory| memory.write(&mut *caller, offset, data)) {
+        Some(Ok(())) => len,
+        _ => -1,
+    }
+}
+
+/// Wait for a query of the database, the module runs on a blocking thread
+fn query(
+    caller: &Caller<'_, HostState>,
+    query: impl std::future::Future<Output = Result<serde_json::Value, Error>>,
+) -> Option<Vec<u8>> {
+    match caller.data().runtime.block_on(query) {
+        Ok(value) => Some(value.to_string().into_bytes()),
+        Err(err) => {
+            tracing::error!("Plugin {}: {}", caller.data().plugin, err);
+            None
+        }
+    }
+}
+
+/// Functions of the `lucle` module imported by plugins
+fn link(engine: &Engine) -> Result<Linker<HostState>, wasmi::Error> {
+    let mut linker = Linker::new(engine);
+
+    // Registration, returning the id of the route
+    linker.func_wrap(
+        HOST_MODULE,
+        "grpc_method",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> i32 {
+            let path = read_string(&caller, ptr, len);
+            let Some(registration) = caller.data_mut().registration.as_mut() else {
+                return -1;
+            };
+            match path {
+                Some(path) if crate::plugin::is_grpc_method(&path) => {
+                    let route = registration.route();
+                    registration.grpc.push((path, route));
+                    route as i32
+                }
+                path => {
+                    registration
+                        .errors
+                        .push(format!("Invalid gRPC method {:?}", path));
+                    -1
+                }
+            }
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "http_route",
+        |mut caller: Caller<'_, HostState>,
+         method_ptr: i32,
+         method_len: i32,
+         path_ptr: i32,
+         path_len: i32|
+         -> i32 {
+            let method = read_string(&caller, method_ptr, method_len);
+            let path = read_string(&caller, path_ptr, path_len);
+            let Some(registration) = caller.data_mut().registration.as_mut() else {
+                return -1;
+            };
+            match (method, path) {
+                (Some(method), Some(path)) if crate::plugin::is_http_route(&method, &path) => {
+                    let route = registration.route();
+                    registration.http.push((method, path, route));
+                    route as i32
+                }
+                (method, path) => {
+                    registration
+                        .errors
+                        .push(format!("Invalid HTTP route {:?} {:?}", method, path));
+                    -1
+                }
+            }
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "hook",
+        |mut caller: Caller<'_, HostState>, event: i32| -> i32 {
+            let Some(registration) = caller.data_mut().registration.as_mut() else {
+                return -1;
+            };
+            match u32::try_from(event) {
+                Ok(event) if crate::plugin::is_hook_event(event) => {
+                    registration.hooks.push(event);
+                    0
+                }
+                _ => {
+                    registration
+                        .errors
+                        .push(format!("Unknown hook event {}", event));
+                    -1
+                }
+            }
+        },
+    )?;
+
+    // Request being served
+    linker.func_wrap(
+        HOST_MODULE,
+        "request",
+        |mut caller: Caller<'_, HostState>, ptr: i32, capacity: i32| -> i32 {
+            let Some(call) = caller.data().call.as_ref() else {
+                return -1;
+            };
+            let request = json!({
+                "method": call.method,
+                "path": call.path,
+                "username": call.username,
+            })
+            .to_string();
+            write(&mut caller, ptr, capacity, request.as_bytes())
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "request_body",
+        |mut caller: Caller<'_, HostState>, ptr: i32, capacity: i32| -> i32 {
+            let Some(body) = caller
+                .data_mut()
+                .call
+                .as_mut()
+                .map(|call| std::mem::take(&mut call.body))
+            else {
+                return -1;
+            };
+            let written = write(&mut caller, ptr, capacity, &body);
+            if let Some(call) = caller.data_mut().call.as_mut() {
+                call.body = body;
+            }
+            written
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "respond",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> i32 {
+            if caller.data().call.is_none() {
+                return -1;
+            }
+            match read(&caller, ptr, len) {
+                Some(body) => {
+                    caller.data_mut().reply.body = body;
+                    0
+                }
+                None => -1,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "content_type",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> i32 {
+            if caller.data().call.is_none() {
+                return -1;
+            }
+            match read_string(&caller, ptr, len) {
+                Some(content_type) => {
+                    caller.data_mut().reply.content_type = Some(content_type);
+                    0
+                }
+                None => -1,
+            }
+        },
+    )?;
+
+    // Configuration of the plugin, the `[config]` table of its manifest
+    linker.func_wrap(
+        HOST_MODULE,
+        "config",
+        |mut caller: Caller<'_, HostState>,
+         key_ptr: i32,
+         key_len: i32,
+         ptr: i32,
+         capacity: i32|
+         -> i32 {
+            let value = read_string(&caller, key_ptr, key_len)
+                .and_then(|key| caller.data().config.get(&key).cloned());
+            match value {
+                Some(value) => write(&mut caller, ptr, capacity, value.as_bytes()),
+                None => -1,
+            }
+        },
+    )?;
+
+    // Read-only queries, answered in JSON
+    linker.func_wrap(
+        HOST_MODULE,
+        "users",
+        |mut caller: Caller<'_, HostState>, ptr: i32, capacity: i32| -> i32 {
+            let storage = caller.data().storage.clone();
+            let users = query(&caller, async move {
+                let users = storage.get()?.list_users().await?;
+                Ok(users
+                    .iter()
+                    .map(|user| {
+                        json!({
+                            "username": user.username,
+                            "role": user.role.as_str(),
+                            "created_at": user.created_at,
+                        })
+                    })
+                    .collect())
+            });
+            match users {
+                Some(users) => write(&mut caller, ptr, capacity, &users),
+                None => -1,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "repository",
+        |mut caller: Caller<'_, HostState>,
+         name_ptr: i32,
+         name_len: i32,
+         ptr: i32,
+         capacity: i32|
+         -> i32 {
+            let Some(name) = read_string(&caller, name_ptr, name_len) else {
+                return -1;
+            };
+            let storage = caller.data().storage.clone();
+            let repository = query(&caller, async move {
+                let storage = storage.get()?;
+                let repository = storage
+                    .get_repository(&name)
+                    .await?
+                    .ok_or(Error::RepositoryNotFound(name.clone()))?;
+                let owner = match repository.owner_id {
+                    Some(owner_id) => storage.get_user_by_id(owner_id).await?,
+                    None => None,
+                };
+                let mut members = Vec::new();
+                for member in storage.list_members(&name).await? {
+                    if let Some(user) = storage.get_user_by_id(member.user_id).await? {
+                        members.push(json!({
+                            "username": user.username,
+                            "permission": member.permission.as_str(),
+                        }));
+                    }
+                }
+                Ok(json!({
+                    "name": repository.name,
+                    "created_at": repository.created_at,
+                    "owner": owner.map(|owner| owner.username),
+                    "members": members,
+                }))
+            });
+            match repository {
+                Some(repository) => write(&mut caller, ptr, capacity, &repository),
+                None => -1,
+            }
+        },
+    )?;
+
+    // Event sent to the ServerStreamingEcho clients
+    linker.func_wrap(
+        HOST_MODULE,
+        "emit",
+        |caller: Caller<'_, HostState>,
+         event_ptr: i32,
+         event_len: i32,
+         data_ptr: i32,
+         data_len: i32|
+         -> i32 {
+            let (Some(event), Some(data)) = (
+                read_string(&caller, event_ptr, event_len),
+                read_string(&caller, data_ptr, data_len),
+            ) else {
+                return -1;
+            };
+            let state = caller.data();
+            // No subscriber is not an error
+            let _ = state
+                .events
+                .send((state.plugin.clone(), PluginEvent::Emitted { event, data }));
+            0
+        },
+    )?;
+
+    Ok(linker)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: WasmLimits = WasmLimits {
+        fuel: 100_000,
+        max_memory: 2 * 65536,
+    };
+
+    /// Compile `body`, the imports and exports of a module besides the ABI
+    /// functions, and load it
+    async fn load(name: &str, body: &str, limits: WasmLimits) -> Result<WasmModule, Error> {
+        let wat = format!(
+            r#"(module
+                (import "lucle" "request" (func $request (param i32 i32) (result i32)))
+                (import "lucle" "request_body" (func $request_body (param i32 i32) (result i32)))
+                (import "lucle" "respond" (func $respond (param i32 i32) (result i32)))
+                (import "lucle" "config" (func $config (param i32 i32 i32 i32) (result i32)))
+                (func (export "lucle_plugin_abi_version") (result i32) (i32.const {}))
+                (func (export "lucle_plugin_register_v1") (result i32) (i32.const 0))
+                {}
+            )"#,
+            ABI_VERSION, body
+        );
+        let path = std::env::temp_dir().join(format!("lucle-{}-{}.wasm", name, std::process::id()));
+        std::fs::write(&path, wat::parse_str(wat).unwrap()).unwrap();
+        let config = HashMap::from([("name".to_string(), "echo".to_string())]);
+        let host = WasmHost {
+            storage: SharedStorage::new(None),
+            events: broadcast::channel(1).0,
+            limits,
+        };
+        let name = name.to_string();
+        tokio::task::spawn_blocking(move || WasmModule::load(&name, &path, config, host))
+            .await
+            .unwrap()
+            .map(|(module, _)| module)
+    }
+
+    fn call(module: &WasmModule, route: u32) -> Result<PluginReply, Error> {
+        let call = PluginCall {
+            method: "POST".to_string(),
+            path: "/".to_string(),
+            username: None,
+            body: b"data".to_vec(),
+        };
+        module.call(route, call)
+    }
+
+    #[tokio::test]
+    async fn infinite_loops_run_out_of_fuel() {
+        let module = load(
+            "wasm-fuel",
+            r#"(memory (export "memory") 1)
+            (func (export "lucle_plugin_handle_v1") (param $route i32) (result i32)
+                (if (i32.eq (local.get $route) (i32.const 1))
+                    (then (loop $forever (br $forever))))
+                (i32.const 200))"#,
+            LIMITS,
+        )
+        .await
+        .unwrap();
+        assert!(matches!(call(&module, 1), Err(Error::Plugin(_))));
+        // Each call gets its own fuel
+        assert_eq!(call(&module, 2).unwrap().status, 200);
+    }
+
+    #[tokio::test]
+    async fn memory_cannot_grow_past_the_limit() {
+        let module = load(
+            "wasm-memory",
+            r#"(memory (export "memory") 1)
+            (func (export "lucle_plugin_handle_v1") (param i32) (result i32)
+                (memory.grow (i32.const 1)))"#,
+            LIMITS,
+        )
+        .await
+        .unwrap();
+        // Returns the previous size in pages, or -1
+        assert_eq!(call(&module, 1).unwrap().status, 1);
+        assert_eq!(call(&module, 1).unwrap().status, u32::MAX);
+
+        let result = load(
+            "wasm-memory-initial",
+            r#"(memory (export "memory") 3)"#,
+            LIMITS,
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Plugin(_))));
+    }
+
+    #[tokio::test]
+    async fn buffers_out_of_memory_are_refused() {
+        // Buffers at 65534 cross the end of the single page of memory
+        let module = load(
+            "wasm-bounds",
+            r#"(memory (export "memory") 1)
+            (data (i32.const 0) "name")
+            (func (export "lucle_plugin_handle_v1") (param $route i32) (result i32)
+                (if (i32.eq (local.get $route) (i32.const 1))
+                    (then (return (call $respond (i32.const 65534) (i32.const 4)))))
+                (if (i32.eq (local.get $route) (i32.const 2))
+                    (then (return (call $respond (i32.const -1) (i32.const 4)))))
+                (if (i32.eq (local.get $route) (i32.const 3))
+                    (then (return (call $request_body (i32.const 65534) (i32.const 16)))))
+                (if (i32.eq (local.get $route) (i32.const 4))
+                    (then (return (call $request (i32.const 65534) (i32.const 1024)))))
+                (if (i32.eq (local.get $route) (i32.const 5))
+                    (then (return (call $config (i32.const 65534) (i32.const 4) (i32.const 0) (i32.const 16)))))
+                (if (i32.eq (local.get $route) (i32.const 6))
+                    (then (return (call $config (i32.const 0) (i32.const 4) (i32.const 65534) (i32.const 16)))))
+                (call $respond (i32.const 0) (i32.const 4)))"#,
+            LIMITS,
+        )
+        .await
+        .unwrap();
+        for route in 1..=6 {
+            let reply = call(&module, route).unwrap();
+            assert_eq!(reply.status, u32::MAX, "route {}", route);
+            assert!(reply.body.is_empty());
+        }
+        // The same calls within the memory work
+        let reply = call(&module, 7).unwrap();
+        assert_eq!(
+            (reply.status, reply.body.as_slice()),
+            (0, b"name".as_slice())
+        );
+    }
+}
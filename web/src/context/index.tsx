@@ -25,7 +25,7 @@ import PropTypes from "prop-types";
 
 // RPC Connect
 import { createGrpcWebTransport } from "@connectrpc/connect-web";
-import { createPromiseClient } from "@connectrpc/connect";
+import { createPromiseClient, Interceptor } from "@connectrpc/connect";
 import { Lucle } from "gen/lucle_connect";
 
 // Material Dashboard 2 React main context
@@ -97,9 +97,17 @@ function MaterialUIControllerProvider({ children }) {
   return <MaterialUI.Provider value={value}>{children}</MaterialUI.Provider>;
 }
 
+// Send the access token issued by login with every call
+const authInterceptor: Interceptor = (next) => async (req) => {
+  const token = localStorage.getItem("token");
+  if (token) req.header.set("Authorization", `Bearer ${token}`);
+  return await next(req);
+};
+
 function LucleRPCProvider({ children }) {
   const transport = createGrpcWebTransport({
     baseUrl: `http://0.0.0.0:8080`,
+    interceptors: [authInterceptor],
   });
   const client = createPromiseClient(Lucle, transport);
   return <LucleRPC.Provider value={client}>{children}</LucleRPC.Provider>;
@@ -3,6 +3,7 @@ import { Navigate, Outlet } from "react-router-dom";
 import Landing from "views/Landing";
 import Install from "layouts/Install";
 import ForgotPassword from "views/ForgotPassword";
+import ResetPassword from "views/ResetPassword";
 import AdminIndex from "views/AdminIndex";
 import Speedupdate from "views/Speedupdate";
 import Login from "views/Login";
@@ -31,6 +32,7 @@ const routes = (isInstalled: boolean) => [
     children: [
       { path: "/login", element: <Login /> },
       { path: "/forgot", element: <ForgotPassword /> },
+      { path: "/reset-password", element: <ResetPassword /> },
       {
         element: <PrivateRoutes />,
         children: [
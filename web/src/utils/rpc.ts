@@ -63,6 +63,7 @@ export const createUser = async (
     password: user_password,
     email: user_mail,
     role,
+    locale: navigator.language,
   });
   if (error) throw error;
 };
@@ -91,3 +92,140 @@ export const listRepositories = async (client: any, username: string) =>
       .then((list) => resolve(list))
       .catch((err) => reject(err));
   });
+
+export const confirmPasswordReset = async (
+  client: any,
+  token: string,
+  new_password: string,
+) => {
+  const { error } = await client.confirm_password_reset({
+    token,
+    newPassword: new_password,
+  });
+  if (error) throw error;
+};
+
+export const listPendingMembers = async (client: any, repo: string) =>
+  new Promise((resolve, reject) => {
+    client
+      .list_pending_members({
+        path: repo,
+      })
+      .then((pending) => resolve(pending.usernames))
+      .catch((err) => reject(err));
+  });
+
+export const approveMember = async (
+  client: any,
+  repository: string,
+  username: string,
+  permission: "read" | "write",
+) => {
+  await client.approve_member({ repository, username, permission });
+};
+
+export const rejectMember = async (
+  client: any,
+  repository: string,
+  username: string,
+) => {
+  await client.reject_member({ repository, username });
+};
+
+export const setMemberPermission = async (
+  client: any,
+  repository: string,
+  username: string,
+  permission: "read" | "write",
+) => {
+  await client.set_member_permission({ repository, username, permission });
+};
+
+export const removeMember = async (
+  client: any,
+  repository: string,
+  username: string,
+) => {
+  await client.remove_member({ repository, username });
+};
+
+export const getRepository = async (client: any, repo: string) =>
+  client.get_repository({ path: repo });
+
+export const deleteRepository = async (client: any, repo: string) => {
+  await client.delete_repository({ path: repo });
+};
+
+export const renameRepository = async (
+  client: any,
+  repo: string,
+  new_repo: string,
+) => {
+  await client.rename_repository({ path: repo, newPath: new_repo });
+};
+
+export const transferRepository = async (
+  client: any,
+  repo: string,
+  username: string,
+) => {
+  await client.transfer_repository({ path: repo, username });
+};
+
+export const listPlugins = async (client: any) =>
+  (await client.list_plugins({})).plugins;
+
+export const enablePlugin = async (client: any, name: string) =>
+  client.enable_plugin({ name });
+
+export const disablePlugin = async (client: any, name: string) =>
+  client.disable_plugin({ name });
+
+export const unloadPlugin = async (client: any, name: string) => {
+  await client.unload_plugin({ name });
+};
+
+export type PageContent = {
+  slug: string;
+  title: string;
+  body: string;
+  status: "draft" | "published";
+};
+
+export const listPages = async (client: any) =>
+  (await client.list_pages({})).pages;
+
+export const getPage = async (client: any, slug: string) =>
+  client.get_page({ slug });
+
+export const createPage = async (client: any, page: PageContent) =>
+  client.create_page(page);
+
+// Resolves to { page, conflicts }, conflicts is set when nothing was saved
+export const updatePage = async (
+  client: any,
+  slug: string,
+  page: PageContent,
+  baseRevision: number,
+) => client.update_page({ slug, page, baseRevision });
+
+export const deletePage = async (client: any, slug: string) => {
+  await client.delete_page({ slug });
+};
+
+export const listPageRevisions = async (client: any, slug: string) =>
+  (await client.list_page_revisions({ slug })).revisions;
+
+export const diffPageRevisions = async (
+  client: any,
+  slug: string,
+  fromRevision: number,
+  toRevision: number,
+) =>
+  (await client.diff_page_revisions({ slug, fromRevision, toRevision })).patch;
+
+export const rollbackPage = async (
+  client: any,
+  slug: string,
+  revision: number,
+) => client.rollback_page({ slug, revision });
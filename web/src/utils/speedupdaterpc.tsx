@@ -127,12 +127,61 @@ export const unregisterPackage = async (
       .catch((error: string) => reject(error));
   });
 
-export const fileToDelete = async (client: any, file: string) =>
+export const fileToDelete = async (client: any, path: string, file: string) =>
   new Promise((resolve, reject) => {
     client
-      .delete_file({
-        file,
-      })
+      .delete_file(
+        {
+          path,
+          file,
+        },
+        { headers },
+      )
+      .then(() => resolve())
+      .catch((error: string) => reject(error));
+  });
+
+export const addTrustedKey = async (
+  client: any,
+  path: string,
+  publicKey: string,
+) =>
+  new Promise((resolve, reject) => {
+    client
+      .add_trusted_key(
+        {
+          path,
+          publicKey,
+        },
+        { headers },
+      )
+      .then((key: any) => resolve(key.id))
+      .catch((error: string) => reject(error));
+  });
+
+export const removeTrustedKey = async (client: any, path: string, id: string) =>
+  new Promise((resolve, reject) => {
+    client
+      .remove_trusted_key(
+        {
+          path,
+          id,
+        },
+        { headers },
+      )
       .then(() => resolve())
       .catch((error: string) => reject(error));
   });
+
+export const listTrustedKeys = async (client: any, path: string) =>
+  new Promise((resolve, reject) => {
+    client
+      .list_trusted_keys(
+        {
+          path,
+        },
+        { headers },
+      )
+      .then((response: any) => resolve(response.keys))
+      .catch((error: string) => reject(error));
+  });
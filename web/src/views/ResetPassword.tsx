@@ -0,0 +1,71 @@
+import { useState, useContext } from "react";
+import { useNavigate, useSearchParams } from "react-router-dom";
+
+import TextField from "@mui/material/TextField";
+import Button from "@mui/material/Button";
+
+// Context
+import { LucleRPC } from "context";
+
+// RPC
+import { confirmPasswordReset } from "utils/rpc";
+
+function ResetPassword() {
+  const [password, setPassword] = useState<string>("");
+  const [confirmPassword, setConfirmPassword] = useState<string>("");
+  const [error, setError] = useState<string>("");
+  const [searchParams] = useSearchParams();
+  const navigate = useNavigate();
+  const client = useContext(LucleRPC);
+
+  const handleReset = () => {
+    setError("");
+    if (password !== confirmPassword || !password) {
+      setError("Password doesn't match");
+      return;
+    }
+    confirmPasswordReset(client, searchParams.get("token") ?? "", password)
+      .then(() => navigate("/login"))
+      .catch((err) => setError(err.rawMessage));
+  };
+
+  return (
+    <div>
+      <TextField
+        margin="normal"
+        required
+        fullWidth
+        id="password"
+        label="New password"
+        type="password"
+        autoComplete="new-password"
+        autoFocus
+        value={password}
+        onChange={(event) => setPassword(event.target.value)}
+      />
+      <TextField
+        margin="normal"
+        required
+        fullWidth
+        id="confirmPassword"
+        label="Confirm password"
+        type="password"
+        autoComplete="new-password"
+        value={confirmPassword}
+        onChange={(event) => setConfirmPassword(event.target.value)}
+      />
+      <Button
+        type="submit"
+        fullWidth
+        variant="contained"
+        sx={{ mt: 3, mb: 2 }}
+        onClick={handleReset}
+      >
+        Reset password
+      </Button>
+      {error}
+    </div>
+  );
+}
+
+export default ResetPassword;
@@ -241,7 +241,7 @@ function Speedupdate() {
           (err) => setError(err.rawMessage),
         );
       }
-      fileToDelete(client, listPackages[row].name).catch((err) =>
+      fileToDelete(client, currentRepo, listPackages[row].name).catch((err) =>
         setError(err.rawMessage),
       );
       setSelectedPackages([]);